@@ -8,14 +8,20 @@ use std::iter::IntoIterator;
 use std::thread::sleep;
 use std::time::Duration;
 
-use crate::authenticator_delegate::{AuthenticatorDelegate, PollError, PollInformation};
+use crate::authenticator_delegate::{
+    AuthenticatorDelegate, DefaultAuthenticatorDelegate, PollError, PollInformation,
+};
 use crate::device::{DeviceFlow, GOOGLE_DEVICE_CODE_URL};
-use crate::installed::{InstalledFlow, InstalledFlowReturnMethod};
+use crate::installed::{AuthParams, InstalledFlow, InstalledFlowReturnMethod};
 use crate::refresh::{RefreshFlow, RefreshResult};
-use crate::storage::TokenStorage;
-use crate::types::{ApplicationSecret, FlowType, RequestError, StringError, Token};
+use crate::storage::{hash_scopes, MemoryStorage, StoredToken, TokenStorage};
+use crate::types::{
+    ApplicationSecret, FlowType, PartialConsentError, RequestError, StringError, Token,
+};
 
+use chrono::Utc;
 use hyper;
+use url::Url;
 
 /// A generalized authenticator which will keep tokens valid and store them.
 ///
@@ -39,6 +45,11 @@ pub struct Authenticator<D, S, C> {
     storage: S,
     client: C,
     secret: ApplicationSecret,
+    redirect_uri: Option<String>,
+    auth_params: AuthParams,
+    incremental: bool,
+    expiry_skew: Duration,
+    retry_policy: RetryPolicy,
 }
 
 /// A provider for authorization tokens, yielding tokens valid for a given scope.
@@ -85,10 +96,256 @@ where
             storage: storage,
             client: client,
             secret: secret.clone(),
+            redirect_uri: None,
+            auth_params: AuthParams::default(),
+            incremental: false,
+            expiry_skew: Duration::from_secs(0),
+            retry_policy: RetryPolicy::default(),
         }
     }
 
-    fn do_installed_flow(&mut self, scopes: &Vec<&str>) -> Result<Token, Box<Error>> {
+    /// Blocks until a token for `account` was retrieved from storage, from the server, or until
+    /// the delegate decided to abort the attempt, or the user decided not to authorize the
+    /// application.
+    /// In any failure case, the delegate will be provided with additional information, and
+    /// the caller will be informed about storage related errors.
+    /// Otherwise it is guaranteed to be valid for the given scopes.
+    ///
+    /// `account` identifies the user the token belongs to, usually by email address, and is
+    /// passed to the authorization server as login hint if a new token has to be obtained.
+    /// The empty string denotes the default account used by `GetToken::token()`.
+    ///
+    /// If the user grants only some of the requested scopes, a `PartialConsentError` is
+    /// returned, which contains the token obtained for the granted scopes.
+    pub fn token_for<'b, I, T>(&mut self, account: &str, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        let (scope_key, scopes) = {
+            let mut sv: Vec<&str> = scopes
+                .into_iter()
+                .map(|s| s.as_ref())
+                .collect::<Vec<&str>>();
+            sv.sort();
+            let mut sh = DefaultHasher::new();
+            &sv.hash(&mut sh);
+            let sv = sv;
+            (sh.finish(), sv)
+        };
+
+        // With incremental authorization, the grant of the account covering the most specific
+        // superset of the scopes is used, and new grants include all previously granted scopes.
+        let grants: Vec<StoredToken> = if self.incremental {
+            self.storage
+                .entries()?
+                .into_iter()
+                .filter(|e| e.account == account)
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let mut all_scopes = scopes.clone();
+        all_scopes.extend(
+            grants
+                .iter()
+                .flat_map(|e| e.scopes.iter().map(|s| s.as_str())),
+        );
+        all_scopes.sort();
+        all_scopes.dedup();
+        let (flow_key, flow_scopes) = hash_scopes(&all_scopes);
+        let (scope_key, scopes) = match grants
+            .iter()
+            .filter(|e| scopes.iter().all(|s| e.scopes.iter().any(|g| g == s)))
+            .min_by_key(|e| e.scopes.len())
+        {
+            Some(grant) => (
+                grant.scope_hash,
+                grant.scopes.iter().map(|s| s.as_str()).collect(),
+            ),
+            None => (scope_key, scopes),
+        };
+
+        // Get cached token. Yes, let's do an explicit return
+        loop {
+            // An expired token without refresh token is useless; a new one has to be obtained.
+            let skew = self.expiry_skew;
+            let stored = self
+                .storage
+                .get_for_account(account, scope_key, &scopes)
+                .map(|t| t.filter(|t| !(t.refresh_token.is_empty() && expires_within(t, skew))));
+            return match stored {
+                Ok(Some(mut t)) => {
+                    // t needs refresh ?
+                    if expires_within(&t, self.expiry_skew) {
+                        let mut rf = RefreshFlow::new(self.client.borrow_mut());
+                        let mut retries = 0;
+                        loop {
+                            match *rf.refresh_token(
+                                self.flow_type.clone(),
+                                &self.secret,
+                                &t.refresh_token,
+                            ) {
+                                RefreshResult::Error(ref err) => {
+                                    match self.retry_policy.connection_error(
+                                        &mut retries,
+                                        &mut self.delegate,
+                                        err,
+                                    ) {
+                                        Retry::Abort | Retry::Skip => {
+                                            return Err(Box::new(StringError::new(
+                                                err.description().to_string(),
+                                                None,
+                                            )));
+                                        }
+                                        Retry::After(d) => sleep(d),
+                                    }
+                                }
+                                RefreshResult::RefreshError(ref err_str, ref err_description) => {
+                                    self.delegate.token_refresh_failed(err_str, err_description);
+                                    let storage_err = match self
+                                        .storage
+                                        .set_for_account(account, scope_key, &scopes, None)
+                                    {
+                                        Ok(_) => String::new(),
+                                        Err(err) => err.to_string(),
+                                    };
+                                    return Err(Box::new(StringError::new(
+                                        storage_err + err_str,
+                                        err_description.as_ref(),
+                                    )));
+                                }
+                                RefreshResult::Success(ref new_t) => {
+                                    // The new token carries a rotated refresh token, if the
+                                    // server issued one; storing it is essential, as the old
+                                    // one may have been invalidated.
+                                    let scope = t.scope.take();
+                                    t = new_t.clone();
+                                    if t.scope.is_none() {
+                                        t.scope = scope;
+                                    }
+                                    loop {
+                                        if let Err(err) = self.storage.set_for_account(
+                                            account,
+                                            scope_key,
+                                            &scopes,
+                                            Some(t.clone()),
+                                        ) {
+                                            match self.delegate.token_storage_failure(true, &err) {
+                                                Retry::Skip => break,
+                                                Retry::Abort => return Err(Box::new(err)),
+                                                Retry::After(d) => {
+                                                    sleep(d);
+                                                    continue;
+                                                }
+                                            }
+                                        }
+                                        break; // .set()
+                                    }
+                                    break; // refresh_token loop
+                                }
+                            } // RefreshResult handling
+                        } // refresh loop
+                    } // handle expiration
+                    Ok(t)
+                }
+                Ok(None) => {
+                    // Nothing was in storage - get a new token
+                    // get new token. The respective sub-routine will do all the logic.
+                    let scopes = &flow_scopes;
+                    match match self.flow_type.clone() {
+                        FlowType::Device(url) => self.retrieve_device_token(account, scopes, url),
+                        FlowType::InstalledInteractive => self.do_installed_flow(account, scopes),
+                        FlowType::InstalledRedirect(_) => self.do_installed_flow(account, scopes),
+                    } {
+                        Ok(token) => {
+                            // With granular consent, the user may have declined some of the
+                            // scopes. Such a token is stored for the scopes actually granted,
+                            // so that it is not mistaken for one valid for all of them.
+                            let missing = missing_scopes(scopes, &token);
+                            let granted = token.granted_scopes().unwrap_or_default();
+                            let (store_key, store_scopes) = if missing.is_empty() {
+                                (flow_key, scopes.clone())
+                            } else {
+                                hash_scopes(&granted)
+                            };
+                            loop {
+                                if let Err(err) = self.storage.set_for_account(
+                                    account,
+                                    store_key,
+                                    &store_scopes,
+                                    Some(token.clone()),
+                                ) {
+                                    match self.delegate.token_storage_failure(true, &err) {
+                                        Retry::Skip => break,
+                                        Retry::Abort => return Err(Box::new(err)),
+                                        Retry::After(d) => {
+                                            sleep(d);
+                                            continue;
+                                        }
+                                    }
+                                }
+                                break;
+                            } // end attempt to save
+
+                            // The new grant supersedes the ones it covers. Failing to remove
+                            // them is harmless, as they merely stay redundant.
+                            for grant in &grants {
+                                if grant.scope_hash != store_key
+                                    && grant
+                                        .scopes
+                                        .iter()
+                                        .all(|s| store_scopes.contains(&s.as_str()))
+                                {
+                                    let grant_scopes =
+                                        grant.scopes.iter().map(|s| s.as_str()).collect();
+                                    let _ = self.storage.set_for_account(
+                                        account,
+                                        grant.scope_hash,
+                                        &grant_scopes,
+                                        None,
+                                    );
+                                }
+                            }
+                            if missing.is_empty() {
+                                Ok(token)
+                            } else {
+                                Err(Box::new(PartialConsentError {
+                                    missing_scopes: missing.iter().map(|s| s.to_string()).collect(),
+                                    token,
+                                }))
+                            }
+                        }
+                        Err(err) => Err(err),
+                    } // end match token retrieve result
+                }
+                Err(err) => match self.delegate.token_storage_failure(false, &err) {
+                    Retry::Abort | Retry::Skip => Err(Box::new(err)),
+                    Retry::After(d) => {
+                        sleep(d);
+                        continue;
+                    }
+                },
+            }; // end match
+        } // end loop
+    }
+
+    /// Returns all accounts for which tokens are stored.
+    pub fn accounts(&self) -> Result<Vec<String>, S::Error> {
+        self.storage.accounts()
+    }
+
+    /// Forgets all tokens of `account`. The next call to `token_for()` for this account will
+    /// ask the user for authorization again.
+    pub fn remove_account(&mut self, account: &str) -> Result<(), S::Error> {
+        self.storage.remove_account(account)
+    }
+
+    fn do_installed_flow(
+        &mut self,
+        account: &str,
+        scopes: &Vec<&str>,
+    ) -> Result<Token, Box<Error>> {
         let installed_type;
 
         match self.flow_type {
@@ -102,18 +359,34 @@ where
         }
 
         let mut flow = InstalledFlow::new(self.client.borrow_mut(), installed_type);
+        if !account.is_empty() {
+            flow = flow.with_login_hint(account);
+        }
+        if let Some(ref redirect_uri) = self.redirect_uri {
+            flow = flow.with_redirect_uri(redirect_uri);
+        }
+        let mut params = self.auth_params.clone();
+        if self.incremental {
+            params = params.with_include_granted_scopes();
+        }
+        flow = flow.with_auth_params(params);
         flow.obtain_token(&mut self.delegate, &self.secret, scopes.iter())
     }
 
     fn retrieve_device_token(
         &mut self,
+        account: &str,
         scopes: &Vec<&str>,
         code_url: String,
     ) -> Result<Token, Box<Error>> {
         let mut flow = DeviceFlow::new(self.client.borrow_mut(), &self.secret, &code_url);
+        if !account.is_empty() {
+            flow = flow.with_login_hint(account);
+        }
 
         // PHASE 1: REQUEST CODE
         let pi: PollInformation;
+        let mut retries = 0;
         loop {
             let res = flow.request_code(scopes.iter());
 
@@ -121,7 +394,11 @@ where
                 Err(res_err) => {
                     match res_err {
                         RequestError::HttpError(err) => {
-                            match self.delegate.connection_error(&err) {
+                            match self.retry_policy.connection_error(
+                                &mut retries,
+                                &mut self.delegate,
+                                &err,
+                            ) {
                                 Retry::Abort | Retry::Skip => {
                                     return Err(Box::new(StringError::from(&err as &Error)));
                                 }
@@ -147,13 +424,18 @@ where
         }
 
         // PHASE 1: POLL TOKEN
+        let mut retries = 0;
         loop {
             match flow.poll_token() {
                 Err(ref poll_err) => {
                     let pts = poll_err.to_string();
                     match poll_err {
                         &&PollError::HttpError(ref err) => {
-                            match self.delegate.connection_error(err) {
+                            match self.retry_policy.connection_error(
+                                &mut retries,
+                                &mut self.delegate,
+                                err,
+                            ) {
                                 Retry::Abort | Retry::Skip => {
                                     return Err(Box::new(StringError::from(err as &Error)));
                                 }
@@ -191,126 +473,13 @@ where
     S: TokenStorage,
     C: BorrowMut<hyper::Client>,
 {
-    /// Blocks until a token was retrieved from storage, from the server, or until the delegate
-    /// decided to abort the attempt, or the user decided not to authorize the application.
-    /// In any failure case, the delegate will be provided with additional information, and
-    /// the caller will be informed about storage related errors.
-    /// Otherwise it is guaranteed to be valid for the given scopes.
+    /// Returns a token for the default account; see `token_for()`.
     fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<Error>>
     where
         T: AsRef<str> + Ord + 'b,
         I: IntoIterator<Item = &'b T>,
     {
-        let (scope_key, scopes) = {
-            let mut sv: Vec<&str> = scopes
-                .into_iter()
-                .map(|s| s.as_ref())
-                .collect::<Vec<&str>>();
-            sv.sort();
-            let mut sh = DefaultHasher::new();
-            &sv.hash(&mut sh);
-            let sv = sv;
-            (sh.finish(), sv)
-        };
-
-        // Get cached token. Yes, let's do an explicit return
-        loop {
-            return match self.storage.get(scope_key, &scopes) {
-                Ok(Some(mut t)) => {
-                    // t needs refresh ?
-                    if t.expired() {
-                        let mut rf = RefreshFlow::new(self.client.borrow_mut());
-                        loop {
-                            match *rf.refresh_token(
-                                self.flow_type.clone(),
-                                &self.secret,
-                                &t.refresh_token,
-                            ) {
-                                RefreshResult::Error(ref err) => {
-                                    match self.delegate.connection_error(err) {
-                                        Retry::Abort | Retry::Skip => {
-                                            return Err(Box::new(StringError::new(
-                                                err.description().to_string(),
-                                                None,
-                                            )));
-                                        }
-                                        Retry::After(d) => sleep(d),
-                                    }
-                                }
-                                RefreshResult::RefreshError(ref err_str, ref err_description) => {
-                                    self.delegate.token_refresh_failed(err_str, err_description);
-                                    let storage_err =
-                                        match self.storage.set(scope_key, &scopes, None) {
-                                            Ok(_) => String::new(),
-                                            Err(err) => err.to_string(),
-                                        };
-                                    return Err(Box::new(StringError::new(
-                                        storage_err + err_str,
-                                        err_description.as_ref(),
-                                    )));
-                                }
-                                RefreshResult::Success(ref new_t) => {
-                                    t = new_t.clone();
-                                    loop {
-                                        if let Err(err) =
-                                            self.storage.set(scope_key, &scopes, Some(t.clone()))
-                                        {
-                                            match self.delegate.token_storage_failure(true, &err) {
-                                                Retry::Skip => break,
-                                                Retry::Abort => return Err(Box::new(err)),
-                                                Retry::After(d) => {
-                                                    sleep(d);
-                                                    continue;
-                                                }
-                                            }
-                                        }
-                                        break; // .set()
-                                    }
-                                    break; // refresh_token loop
-                                }
-                            } // RefreshResult handling
-                        } // refresh loop
-                    } // handle expiration
-                    Ok(t)
-                }
-                Ok(None) => {
-                    // Nothing was in storage - get a new token
-                    // get new token. The respective sub-routine will do all the logic.
-                    match match self.flow_type.clone() {
-                        FlowType::Device(url) => self.retrieve_device_token(&scopes, url),
-                        FlowType::InstalledInteractive => self.do_installed_flow(&scopes),
-                        FlowType::InstalledRedirect(_) => self.do_installed_flow(&scopes),
-                    } {
-                        Ok(token) => {
-                            loop {
-                                if let Err(err) =
-                                    self.storage.set(scope_key, &scopes, Some(token.clone()))
-                                {
-                                    match self.delegate.token_storage_failure(true, &err) {
-                                        Retry::Skip => break,
-                                        Retry::Abort => return Err(Box::new(err)),
-                                        Retry::After(d) => {
-                                            sleep(d);
-                                            continue;
-                                        }
-                                    }
-                                }
-                                break;
-                            } // end attempt to save
-                            Ok(token)
-                        }
-                        Err(err) => Err(err),
-                    } // end match token retrieve result
-                }
-                Err(err) => match self.delegate.token_storage_failure(false, &err) {
-                    Retry::Abort | Retry::Skip => Err(Box::new(err)),
-                    Retry::After(d) => {
-                        sleep(d);
-                        continue;
-                    }
-                },
-            }; // end match
-        } // end loop
+        self.token_for("", scopes)
     }
 
     fn api_key(&mut self) -> Option<String> {
@@ -321,6 +490,283 @@ where
     }
 }
 
+impl Authenticator<DefaultAuthenticatorDelegate, MemoryStorage, hyper::Client> {
+    /// Returns a builder for an `Authenticator` using `secret`.
+    ///
+    /// Unless configured otherwise, the `Authenticator` keeps tokens in a `MemoryStorage`,
+    /// interacts with the user through the `DefaultAuthenticatorDelegate` and makes requests
+    /// with an HTTPS client using native-tls (with the `native-tls` feature, which is enabled by
+    /// default). The flow is chosen based on the secret: the installed flow with a redirect to
+    /// localhost if the secret allows such a redirect URI, and the device flow otherwise.
+    ///
+    /// ```no_run
+    /// # use yup_oauth2::{Authenticator, ApplicationSecret, DiskTokenStorage, GetToken};
+    /// # use std::time::Duration;
+    /// # let secret = ApplicationSecret::default();
+    /// let mut auth = Authenticator::builder(&secret)
+    ///     .with_storage(DiskTokenStorage::new(&"tokens.json".to_string()).unwrap())
+    ///     .with_timeout(Duration::from_secs(30))
+    ///     .build()
+    ///     .unwrap();
+    /// let token = auth.token(&["https://www.googleapis.com/auth/drive"]);
+    /// ```
+    pub fn builder(
+        secret: &ApplicationSecret,
+    ) -> AuthenticatorBuilder<DefaultAuthenticatorDelegate, MemoryStorage, hyper::Client> {
+        AuthenticatorBuilder {
+            secret: secret.clone(),
+            delegate: DefaultAuthenticatorDelegate,
+            storage: MemoryStorage::default(),
+            client: None,
+            default_client: default_client(),
+            flow_type: None,
+            redirect_uri: None,
+            auth_params: AuthParams::default(),
+            incremental: false,
+            timeout: None,
+            expiry_skew: Duration::from_secs(0),
+            retry_policy: RetryPolicy::default(),
+        }
+    }
+}
+
+/// Creates the HTTP client of an `Authenticator` if the builder wasn't given one.
+type ClientFactory<C> = fn() -> Result<C, Box<dyn Error>>;
+
+#[cfg(feature = "native-tls")]
+fn default_client() -> Option<ClientFactory<hyper::Client>> {
+    fn tls_client() -> Result<hyper::Client, Box<dyn Error>> {
+        let tls = hyper_native_tls::NativeTlsClient::new()?;
+        Ok(hyper::Client::with_connector(
+            hyper::net::HttpsConnector::new(tls),
+        ))
+    }
+    Some(tls_client)
+}
+
+#[cfg(not(feature = "native-tls"))]
+fn default_client() -> Option<ClientFactory<hyper::Client>> {
+    None
+}
+
+/// Returns the flow to use for `secret` if none is configured: the installed flow with a
+/// redirect to localhost if the secret allows such a redirect URI, and the device flow
+/// otherwise.
+fn default_flow_type(secret: &ApplicationSecret) -> FlowType {
+    for uri in &secret.redirect_uris {
+        if let Ok(url) = Url::parse(uri) {
+            let localhost = matches!(url.host_str(), Some("localhost") | Some("127.0.0.1"));
+            if url.scheme() == "http" && localhost {
+                return FlowType::InstalledRedirect(u32::from(url.port().unwrap_or(8080)));
+            }
+        }
+    }
+    FlowType::Device(GOOGLE_DEVICE_CODE_URL.to_string())
+}
+
+/// Whether `token` expires within `skew` from now.
+fn expires_within(token: &Token, skew: Duration) -> bool {
+    let skew = chrono::Duration::from_std(skew).unwrap_or_else(|_| chrono::Duration::zero());
+    token.expiry_date() - skew <= Utc::now()
+}
+
+/// Builds an `Authenticator`; see `Authenticator::builder()`.
+pub struct AuthenticatorBuilder<D, S, C> {
+    secret: ApplicationSecret,
+    delegate: D,
+    storage: S,
+    client: Option<C>,
+    default_client: Option<ClientFactory<C>>,
+    flow_type: Option<FlowType>,
+    redirect_uri: Option<String>,
+    auth_params: AuthParams,
+    incremental: bool,
+    timeout: Option<Duration>,
+    expiry_skew: Duration,
+    retry_policy: RetryPolicy,
+}
+
+impl<D, S, C> AuthenticatorBuilder<D, S, C>
+where
+    D: AuthenticatorDelegate,
+    S: TokenStorage,
+    C: BorrowMut<hyper::Client>,
+{
+    /// Uses `delegate` to interact with the user.
+    pub fn with_delegate<D2: AuthenticatorDelegate>(
+        self,
+        delegate: D2,
+    ) -> AuthenticatorBuilder<D2, S, C> {
+        AuthenticatorBuilder {
+            secret: self.secret,
+            delegate,
+            storage: self.storage,
+            client: self.client,
+            default_client: self.default_client,
+            flow_type: self.flow_type,
+            redirect_uri: self.redirect_uri,
+            auth_params: self.auth_params,
+            incremental: self.incremental,
+            timeout: self.timeout,
+            expiry_skew: self.expiry_skew,
+            retry_policy: self.retry_policy,
+        }
+    }
+
+    /// Keeps tokens in `storage`.
+    pub fn with_storage<S2: TokenStorage>(self, storage: S2) -> AuthenticatorBuilder<D, S2, C> {
+        AuthenticatorBuilder {
+            secret: self.secret,
+            delegate: self.delegate,
+            storage,
+            client: self.client,
+            default_client: self.default_client,
+            flow_type: self.flow_type,
+            redirect_uri: self.redirect_uri,
+            auth_params: self.auth_params,
+            incremental: self.incremental,
+            timeout: self.timeout,
+            expiry_skew: self.expiry_skew,
+            retry_policy: self.retry_policy,
+        }
+    }
+
+    /// Makes all requests with `client`.
+    pub fn with_client<C2: BorrowMut<hyper::Client>>(
+        self,
+        client: C2,
+    ) -> AuthenticatorBuilder<D, S, C2> {
+        AuthenticatorBuilder {
+            secret: self.secret,
+            delegate: self.delegate,
+            storage: self.storage,
+            client: Some(client),
+            default_client: None,
+            flow_type: self.flow_type,
+            redirect_uri: self.redirect_uri,
+            auth_params: self.auth_params,
+            incremental: self.incremental,
+            timeout: self.timeout,
+            expiry_skew: self.expiry_skew,
+            retry_policy: self.retry_policy,
+        }
+    }
+
+    /// Uses `flow_type` instead of the flow derived from the secret.
+    pub fn with_flow_type(mut self, flow_type: FlowType) -> AuthenticatorBuilder<D, S, C> {
+        self.flow_type = Some(flow_type);
+        self
+    }
+
+    /// Uses `redirect_uri` in the installed flow, instead of the one returned by
+    /// `AuthenticatorDelegate::redirect_uri()` or the default one.
+    pub fn with_redirect_uri<U: AsRef<str>>(
+        mut self,
+        redirect_uri: U,
+    ) -> AuthenticatorBuilder<D, S, C> {
+        self.redirect_uri = Some(redirect_uri.as_ref().to_string());
+        self
+    }
+
+    /// Adds `params` to the authorization requests of the installed flow, e.g.
+    /// `AuthParams::default().offline()` to reliably receive refresh tokens.
+    pub fn with_auth_params(mut self, params: AuthParams) -> AuthenticatorBuilder<D, S, C> {
+        self.auth_params = params;
+        self
+    }
+
+    /// Enables incremental authorization: a token granted for some scopes is also used for any
+    /// subset of them, and when further scopes are needed, the user is asked to add them to
+    /// the existing grant (with `include_granted_scopes=true`). The combined grant replaces the
+    /// ones it covers, so that one refresh token serves all scopes of an account. This
+    /// requires a storage supporting `TokenStorage::entries()`.
+    pub fn with_incremental_authorization(
+        mut self,
+        incremental: bool,
+    ) -> AuthenticatorBuilder<D, S, C> {
+        self.incremental = incremental;
+        self
+    }
+
+    /// Sets the read and write timeouts of the HTTP client.
+    pub fn with_timeout(mut self, timeout: Duration) -> AuthenticatorBuilder<D, S, C> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Refreshes tokens once they expire within `skew`, so that they don't expire while in use.
+    pub fn with_expiry_skew(mut self, skew: Duration) -> AuthenticatorBuilder<D, S, C> {
+        self.expiry_skew = skew;
+        self
+    }
+
+    /// Retries requests failing with connection errors according to `policy`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> AuthenticatorBuilder<D, S, C> {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Returns the `Authenticator`. Fails if no client was given and the default client can't
+    /// be created, e.g. because the `native-tls` feature is disabled.
+    pub fn build(self) -> Result<Authenticator<D, S, C>, Box<dyn Error>> {
+        let mut client = match (self.client, self.default_client) {
+            (Some(client), _) => client,
+            (None, Some(default_client)) => default_client()?,
+            (None, None) => {
+                return Err(Box::new(StringError::new(
+                    "No HTTP client given, and the native-tls feature is disabled".to_string(),
+                    None,
+                )))
+            }
+        };
+        if let Some(timeout) = self.timeout {
+            client.borrow_mut().set_read_timeout(Some(timeout));
+            client.borrow_mut().set_write_timeout(Some(timeout));
+        }
+
+        let secret = self.secret;
+        Ok(Authenticator {
+            flow_type: self.flow_type.unwrap_or_else(|| default_flow_type(&secret)),
+            delegate: self.delegate,
+            storage: self.storage,
+            client,
+            secret,
+            redirect_uri: self.redirect_uri,
+            auth_params: self.auth_params,
+            incremental: self.incremental,
+            expiry_skew: self.expiry_skew,
+            retry_policy: self.retry_policy,
+        })
+    }
+}
+
+/// Scopes which Google grants under a different name than the requested one.
+const SCOPE_ALIASES: &[(&str, &str)] = &[
+    ("email", "https://www.googleapis.com/auth/userinfo.email"),
+    (
+        "profile",
+        "https://www.googleapis.com/auth/userinfo.profile",
+    ),
+];
+
+/// Returns the requested scopes which the server reports as not granted. If the server didn't
+/// report the granted scopes, all requested scopes are assumed to be granted.
+fn missing_scopes<'a>(requested: &[&'a str], token: &Token) -> Vec<&'a str> {
+    let granted = match token.granted_scopes() {
+        Some(granted) => granted,
+        None => return Vec::new(),
+    };
+    requested
+        .iter()
+        .filter(|scope| {
+            !granted
+                .iter()
+                .any(|g| g == *scope || SCOPE_ALIASES.contains(&(**scope, *g)))
+        })
+        .cloned()
+        .collect()
+}
+
 /// A utility type to indicate how operations DeviceFlowHelper operations should be retried
 pub enum Retry {
     /// Signal you don't want to retry
@@ -332,6 +778,43 @@ pub enum Retry {
     Skip,
 }
 
+/// How often the `Authenticator` retries requests that fail with connection errors, before
+/// asking `AuthenticatorDelegate::connection_error()` how to proceed. By default, requests are
+/// not retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// The number of retries.
+    pub max_retries: u32,
+    /// The time to wait before each retry.
+    pub delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 0,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Decides how to handle the connection error `err`, after `retries` retries so far.
+    fn connection_error<D: AuthenticatorDelegate>(
+        &self,
+        retries: &mut u32,
+        delegate: &mut D,
+        err: &hyper::Error,
+    ) -> Retry {
+        if *retries < self.max_retries {
+            *retries += 1;
+            Retry::After(self.delay)
+        } else {
+            delegate.connection_error(err)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::super::device::tests::MockGoogleAuth;
@@ -340,6 +823,7 @@ mod tests {
     use super::*;
     use crate::authenticator_delegate::DefaultAuthenticatorDelegate;
     use crate::storage::MemoryStorage;
+    use crate::types::tests::MockConnector;
     use hyper;
     use std::default::Default;
 
@@ -365,4 +849,176 @@ mod tests {
             _ => panic!("Expected to retrieve token in one go"),
         }
     }
+
+    #[test]
+    fn partial_consent() {
+        use serde_json as json;
+
+        let secret = json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        let mut auth = Authenticator::new(
+            &secret,
+            DefaultAuthenticatorDelegate,
+            hyper::Client::with_connector(MockGoogleAuth::with_token_response(
+                r#"{"access_token":"partial","expires_in":3920,"token_type":"Bearer",
+                    "refresh_token":"refresh",
+                    "scope":"https://www.googleapis.com/auth/userinfo.email"}"#,
+            )),
+            <MemoryStorage as Default>::default(),
+            None,
+        );
+
+        let err = auth
+            .token(&["email", "https://www.googleapis.com/auth/drive"])
+            .unwrap_err();
+        let err = err.downcast_ref::<PartialConsentError>().unwrap();
+        assert_eq!(
+            err.missing_scopes,
+            vec!["https://www.googleapis.com/auth/drive".to_string()]
+        );
+        assert_eq!(err.token.access_token, "partial");
+
+        // The token is available for the granted scope, without contacting the server.
+        let t = auth
+            .token(&["https://www.googleapis.com/auth/userinfo.email"])
+            .unwrap();
+        assert_eq!(t.access_token, "partial");
+    }
+
+    #[test]
+    fn builder() {
+        use serde_json as json;
+
+        let secret = json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        // The secret doesn't allow redirects to localhost, so the device flow is used.
+        let t = Authenticator::builder(&secret)
+            .with_client(hyper::Client::with_connector(
+                <MockGoogleAuth as Default>::default(),
+            ))
+            .with_timeout(Duration::from_secs(10))
+            .build()
+            .unwrap()
+            .token(&["https://www.googleapis.com/auth/youtube.upload"])
+            .unwrap();
+        assert_eq!(t.access_token, "1/fFAGRNJru1FTz70BzhT3Zg");
+
+        let mut localhost = secret.clone();
+        localhost.redirect_uris = vec!["http://localhost:8088".to_string()];
+        match default_flow_type(&localhost) {
+            FlowType::InstalledRedirect(8088) => (),
+            _ => panic!("Expected the installed flow with redirect"),
+        }
+        localhost.redirect_uris = vec!["http://localhost".to_string()];
+        match default_flow_type(&localhost) {
+            FlowType::InstalledRedirect(8080) => (),
+            _ => panic!("Expected the installed flow with redirect"),
+        }
+    }
+
+    #[test]
+    fn expiry_skew() {
+        use serde_json as json;
+
+        let secret = json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        let scopes = ["https://www.googleapis.com/auth/drive"];
+        let mut storage = MemoryStorage::default();
+        let (hash, scps) = hash_scopes(&scopes);
+        storage
+            .set(
+                hash,
+                &scps,
+                Some(Token {
+                    access_token: "expiring".to_string(),
+                    refresh_token: "refresh".to_string(),
+                    token_type: "Bearer".to_string(),
+                    expires_in: None,
+                    expires_in_timestamp: Some(Utc::now().timestamp() + 30),
+                    scope: None,
+                    id_token: None,
+                }),
+            )
+            .unwrap();
+
+        const REFRESH_RESPONSE: &str = "HTTP/1.1 200 OK\r\n\r\n\
+             {\"access_token\":\"refreshed\",\"token_type\":\"Bearer\",\"expires_in\":3600}";
+        let mut auth = Authenticator::builder(&secret)
+            .with_storage(storage)
+            .with_client(hyper::Client::with_connector(MockConnector::new(&[
+                REFRESH_RESPONSE,
+            ])))
+            .with_expiry_skew(Duration::from_secs(60))
+            .build()
+            .unwrap();
+        assert_eq!(auth.token(&scopes).unwrap().access_token, "refreshed");
+    }
+
+    #[test]
+    fn incremental_authorization() {
+        use serde_json as json;
+
+        let secret = json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        let drive = "https://www.googleapis.com/auth/drive";
+        let email = "https://www.googleapis.com/auth/userinfo.email";
+        // Requesting an already granted scope again must not duplicate it in the new grant.
+        for requested in &[vec![email], vec![email, drive]] {
+            let mut storage = MemoryStorage::default();
+            let granted = [drive];
+            let (hash, scps) = hash_scopes(&granted);
+            storage
+                .set(
+                    hash,
+                    &scps,
+                    Some(Token {
+                        access_token: "drive".to_string(),
+                        refresh_token: "refresh".to_string(),
+                        token_type: "Bearer".to_string(),
+                        expires_in: None,
+                        expires_in_timestamp: Some(Utc::now().timestamp() + 3600),
+                        scope: None,
+                        id_token: None,
+                    }),
+                )
+                .unwrap();
+
+            let mut auth = Authenticator::builder(&secret)
+                .with_storage(storage)
+                .with_client(hyper::Client::with_connector(
+                    <MockGoogleAuth as Default>::default(),
+                ))
+                .with_incremental_authorization(true)
+                .build()
+                .unwrap();
+            assert_eq!(
+                auth.token(requested).unwrap().access_token,
+                "1/fFAGRNJru1FTz70BzhT3Zg"
+            );
+
+            // The combined grant replaced the old one, and serves both scopes without
+            // contacting the server again.
+            let entries = auth.storage.entries().unwrap();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(
+                entries[0].scopes,
+                vec![drive.to_string(), email.to_string()]
+            );
+            assert_eq!(entries[0].scope_hash, hash_scopes(&[drive, email]).0);
+            for scopes in &[vec![drive], vec![email], vec![email, drive]] {
+                assert_eq!(
+                    auth.token(scopes).unwrap().access_token,
+                    "1/fFAGRNJru1FTz70BzhT3Zg"
+                );
+            }
+        }
+    }
 }
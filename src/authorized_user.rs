@@ -0,0 +1,155 @@
+//! This module provides a token source (`GetToken`) for `authorized_user` credentials, as written
+//! by `gcloud auth application-default login`. These consist of an OAuth client and a refresh
+//! token, from which access tokens are minted without any user interaction. This makes them
+//! suitable for non-interactive environments like CI jobs, which must never open a browser.
+
+use std::borrow::BorrowMut;
+use std::error::Error;
+
+use crate::authenticator::GetToken;
+use crate::refresh::{RefreshFlow, RefreshResult};
+use crate::storage::{hash_scopes, MemoryStorage, TokenStorage};
+use crate::types::{ApplicationSecret, FlowType, StringError, Token};
+
+/// Google's token endpoint, used for `authorized_user` credentials, which don't name one
+/// themselves, and for service account keys without a `token_uri`.
+pub const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// JSON schema of `authorized_user` credentials.
+///
+/// You can use `helpers::authorized_user_from_file()` to read them from a file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuthorizedUserKey {
+    #[serde(rename = "type")]
+    pub key_type: Option<String>,
+    pub client_id: String,
+    pub client_secret: String,
+    pub refresh_token: String,
+    /// The project to bill for API usage, if different from the client's project.
+    pub quota_project_id: Option<String>,
+}
+
+/// A token source (`GetToken`) yielding access tokens obtained with the refresh token of
+/// `authorized_user` credentials. Tokens are cached per set of scopes, and renewed once
+/// they expired.
+///
+/// Note that the scopes of the minted tokens are those of the original authorization; the
+/// requested scopes only select the cache entry.
+pub struct AuthorizedUserAccess<C> {
+    client: C,
+    secret: ApplicationSecret,
+    refresh_token: String,
+    cache: MemoryStorage,
+}
+
+impl<C> AuthorizedUserAccess<C>
+where
+    C: BorrowMut<hyper::Client>,
+{
+    /// Returns a new `AuthorizedUserAccess` token source, using `GOOGLE_TOKEN_URI`.
+    pub fn new(key: AuthorizedUserKey, client: C) -> AuthorizedUserAccess<C> {
+        AuthorizedUserAccess::with_token_uri(key, client, GOOGLE_TOKEN_URI)
+    }
+
+    /// Returns a new `AuthorizedUserAccess` token source refreshing tokens at `token_uri`.
+    pub fn with_token_uri<S: AsRef<str>>(
+        key: AuthorizedUserKey,
+        client: C,
+        token_uri: S,
+    ) -> AuthorizedUserAccess<C> {
+        AuthorizedUserAccess {
+            client,
+            secret: ApplicationSecret {
+                client_id: key.client_id,
+                client_secret: key.client_secret,
+                token_uri: token_uri.as_ref().to_string(),
+                ..Default::default()
+            },
+            refresh_token: key.refresh_token,
+            cache: MemoryStorage::default(),
+        }
+    }
+
+    /// Returns the refresh token in use. It differs from the one in the credentials if the
+    /// server rotated it, in which case the credentials should be updated with the new one.
+    pub fn refresh_token(&self) -> &str {
+        &self.refresh_token
+    }
+}
+
+impl<C: BorrowMut<hyper::Client>> GetToken for AuthorizedUserAccess<C> {
+    fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        let (hash, scps) = hash_scopes(scopes);
+
+        if let Some(token) = self.cache.get(hash, &scps)? {
+            if !token.expired() {
+                return Ok(token);
+            }
+        }
+
+        let token = match *RefreshFlow::new(self.client.borrow_mut()).refresh_token(
+            FlowType::InstalledInteractive,
+            &self.secret,
+            &self.refresh_token,
+        ) {
+            RefreshResult::Error(ref err) => {
+                return Err(Box::new(StringError::new(err.to_string(), None)));
+            }
+            RefreshResult::RefreshError(ref err, ref description) => {
+                return Err(Box::new(StringError::new(
+                    err.clone(),
+                    description.as_ref(),
+                )));
+            }
+            RefreshResult::Success(ref token) => token.clone(),
+        };
+        self.refresh_token = token.refresh_token.clone();
+        let _ = self.cache.set(hash, &scps, Some(token.clone()));
+
+        Ok(token)
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::tests::MockConnector;
+
+    const REFRESH_RESPONSE: &str = "HTTP/1.1 200 OK\r\n\
+         Server: BOGUS\r\n\
+         \r\n\
+         {\"access_token\":\"ya29.access\",\"expires_in\":3600,\
+         \"token_type\":\"Bearer\",\"refresh_token\":\"1/rotated\"}";
+
+    const TEST_AUTHORIZED_USER: &str = r#"{
+        "client_id": "764086051850-6qr4p6gpi6hn506pt8ejuq83di341hur.apps.googleusercontent.com",
+        "client_secret": "d-FL95Q19q7MQmFpd7hHD0Ty",
+        "refresh_token": "1/original",
+        "type": "authorized_user"
+    }"#;
+
+    #[test]
+    fn token_from_refresh_token() {
+        let key: AuthorizedUserKey = serde_json::from_str(TEST_AUTHORIZED_USER).unwrap();
+        let mut acc = AuthorizedUserAccess::new(
+            key,
+            hyper::Client::with_connector(MockConnector::new(&[REFRESH_RESPONSE])),
+        );
+
+        let scopes = ["https://www.googleapis.com/auth/cloud-platform"];
+        let token = acc.token(&scopes).unwrap();
+        assert_eq!(token.access_token, "ya29.access");
+        assert_eq!(acc.refresh_token(), "1/rotated");
+
+        // The mock only answers once, so this token has to come from the cache.
+        assert_eq!(acc.token(&scopes).unwrap(), token);
+    }
+}
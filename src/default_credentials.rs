@@ -0,0 +1,254 @@
+//! Discovery of [Application Default
+//! Credentials](https://cloud.google.com/docs/authentication/production), following the same
+//! lookup order as Google's client libraries:
+//!
+//! 1. the credentials file named by the `GOOGLE_APPLICATION_CREDENTIALS` environment variable,
+//! 2. the file written by `gcloud auth application-default login`, usually
+//!    `~/.config/gcloud/application_default_credentials.json`,
+//! 3. the metadata server available on Google Compute Engine and related platforms.
+//!
+//! Credential files are dispatched on their `type` field. Currently, `service_account`,
+//! `authorized_user` and `external_account` credentials are supported.
+
+use std::borrow::BorrowMut;
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::authenticator::GetToken;
+use crate::authorized_user::{AuthorizedUserAccess, AuthorizedUserKey};
+use crate::helper::parse_json;
+use crate::metadata::MetadataServerAccess;
+use crate::service_account::{
+    ExternalAccountAccess, ExternalAccountKey, ServiceAccountAccess, ServiceAccountKey,
+};
+use crate::types::Token;
+
+/// Environment variable naming a credentials file, taking precedence over all other sources.
+pub const CREDENTIALS_ENV_VAR: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
+const WELL_KNOWN_FILE: &str = "application_default_credentials.json";
+
+/// A token source found by `default_credentials()`.
+///
+/// As `GetToken` can't be used as a trait object, the supported kinds of credentials are
+/// collected in this enum, which implements `GetToken` by delegating to the contained source.
+pub enum DefaultCredentials<C> {
+    /// Credentials from a `service_account` key file.
+    ServiceAccount(Box<ServiceAccountAccess<C>>),
+    /// `authorized_user` credentials, usually written by gcloud.
+    AuthorizedUser(AuthorizedUserAccess<C>),
+    /// `external_account` credentials for workload identity federation.
+    ExternalAccount(ExternalAccountAccess<C>),
+    /// The metadata server of the Google Cloud instance we're running on.
+    MetadataServer(MetadataServerAccess<C>),
+}
+
+impl<C: BorrowMut<hyper::Client>> GetToken for DefaultCredentials<C> {
+    fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        match *self {
+            DefaultCredentials::ServiceAccount(ref mut access) => access.token(scopes),
+            DefaultCredentials::AuthorizedUser(ref mut access) => access.token(scopes),
+            DefaultCredentials::ExternalAccount(ref mut access) => access.token(scopes),
+            DefaultCredentials::MetadataServer(ref mut access) => access.token(scopes),
+        }
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        match *self {
+            DefaultCredentials::ServiceAccount(ref mut access) => access.api_key(),
+            DefaultCredentials::AuthorizedUser(ref mut access) => access.api_key(),
+            DefaultCredentials::ExternalAccount(ref mut access) => access.api_key(),
+            DefaultCredentials::MetadataServer(ref mut access) => access.api_key(),
+        }
+    }
+}
+
+/// Looks up the Application Default Credentials in the order described in the module
+/// documentation, and returns a token source using `client` for all requests.
+/// Checking for the metadata server involves a network request, which gives up after
+/// `METADATA_PROBE_TIMEOUT` when not running on Google Cloud.
+///
+/// Returns an error of kind `NotFound` if no credentials could be found, and `InvalidData` if
+/// a credentials file is malformed, of an unsupported type, or contains an invalid key.
+pub fn default_credentials<C: BorrowMut<hyper::Client>>(
+    client: C,
+) -> io::Result<DefaultCredentials<C>> {
+    find_default_credentials(|name| env::var_os(name), client)
+}
+
+/// Like `default_credentials()`, but looks up environment variables with `var`.
+fn find_default_credentials<F, C>(var: F, client: C) -> io::Result<DefaultCredentials<C>>
+where
+    F: Fn(&str) -> Option<OsString>,
+    C: BorrowMut<hyper::Client>,
+{
+    if let Some(path) = var(CREDENTIALS_ENV_VAR) {
+        // Unlike the well-known file, a file named explicitly has to exist.
+        return credentials_from_file(path, client);
+    }
+    if let Some(path) = well_known_file(var) {
+        if path.is_file() {
+            return credentials_from_file(path, client);
+        }
+    }
+    let mut metadata_server = MetadataServerAccess::new(client);
+    if metadata_server.is_available() {
+        return Ok(DefaultCredentials::MetadataServer(metadata_server));
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Could not find default credentials",
+    ))
+}
+
+/// Reads a credentials file of any supported type.
+pub fn credentials_from_file<P: AsRef<Path>, C: BorrowMut<hyper::Client>>(
+    path: P,
+    client: C,
+) -> io::Result<DefaultCredentials<C>> {
+    credentials_from_json(fs::read_to_string(path)?, client)
+}
+
+/// Parses the contents of a credentials file of any supported type.
+pub fn credentials_from_json<S: AsRef<str>, C: BorrowMut<hyper::Client>>(
+    json: S,
+    client: C,
+) -> io::Result<DefaultCredentials<C>> {
+    #[derive(Deserialize)]
+    struct CredentialsType {
+        #[serde(rename = "type")]
+        cred_type: String,
+    }
+
+    let json = json.as_ref();
+    let cred_type: CredentialsType = parse_json(json)?;
+    match cred_type.cred_type.as_str() {
+        "service_account" => {
+            let key: ServiceAccountKey = parse_json(json)?;
+            let access = ServiceAccountAccess::with_algorithm(key, client, None).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Bad service account key: {}", e),
+                )
+            })?;
+            Ok(DefaultCredentials::ServiceAccount(Box::new(access)))
+        }
+        "authorized_user" => {
+            let key: AuthorizedUserKey = parse_json(json)?;
+            Ok(DefaultCredentials::AuthorizedUser(
+                AuthorizedUserAccess::new(key, client),
+            ))
+        }
+        "external_account" => {
+            let key: ExternalAccountKey = parse_json(json)?;
+            Ok(DefaultCredentials::ExternalAccount(
+                ExternalAccountAccess::new(key, client),
+            ))
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported credentials type '{}'", other),
+        )),
+    }
+}
+
+/// The location of the credentials written by `gcloud auth application-default login`, given
+/// the environment variables looked up with `var`.
+fn well_known_file<F: Fn(&str) -> Option<OsString>>(var: F) -> Option<PathBuf> {
+    let config_dir = match var("CLOUDSDK_CONFIG") {
+        Some(dir) => PathBuf::from(dir),
+        None if cfg!(windows) => PathBuf::from(var("APPDATA")?).join("gcloud"),
+        None => PathBuf::from(var("HOME")?).join(".config").join("gcloud"),
+    };
+    Some(config_dir.join(WELL_KNOWN_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dispatch_on_type() {
+        let creds =
+            credentials_from_file("examples/Sanguine-69411a0c0eea.json", hyper::Client::new());
+        match creds {
+            Ok(DefaultCredentials::ServiceAccount(_)) => {}
+            _ => panic!("Expected service account credentials"),
+        }
+
+        let authorized_user = r#"{"type":"authorized_user","client_id":"id",
+            "client_secret":"secret","refresh_token":"refresh"}"#;
+        match credentials_from_json(authorized_user, hyper::Client::new()) {
+            Ok(DefaultCredentials::AuthorizedUser(_)) => {}
+            _ => panic!("Expected authorized user credentials"),
+        }
+
+        match credentials_from_json(r#"{"type":"unknown"}"#, hyper::Client::new()) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+            Ok(_) => panic!("Expected unknown credentials type to be rejected"),
+        }
+    }
+
+    #[test]
+    fn credentials_from_env_var() {
+        let var = |name: &str| match name {
+            CREDENTIALS_ENV_VAR => Some("examples/Sanguine-69411a0c0eea.json".into()),
+            _ => None,
+        };
+        match find_default_credentials(var, hyper::Client::new()) {
+            Ok(DefaultCredentials::ServiceAccount(_)) => {}
+            _ => panic!("Expected service account credentials"),
+        }
+
+        let var = |name: &str| match name {
+            CREDENTIALS_ENV_VAR => Some("examples/missing.json".into()),
+            _ => None,
+        };
+        match find_default_credentials(var, hyper::Client::new()) {
+            Err(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            Ok(_) => panic!("Expected a missing credentials file to be an error"),
+        }
+    }
+
+    #[test]
+    fn credentials_from_well_known_file() {
+        let config_dir = env::temp_dir().join("yup-oauth2-gcloud");
+        fs::create_dir_all(&config_dir).unwrap();
+        fs::write(
+            config_dir.join(WELL_KNOWN_FILE),
+            r#"{"type":"authorized_user","client_id":"id",
+            "client_secret":"secret","refresh_token":"refresh"}"#,
+        )
+        .unwrap();
+
+        let var = |name: &str| match name {
+            "CLOUDSDK_CONFIG" => Some(config_dir.clone().into_os_string()),
+            _ => None,
+        };
+        assert_eq!(well_known_file(var), Some(config_dir.join(WELL_KNOWN_FILE)));
+        match find_default_credentials(var, hyper::Client::new()) {
+            Ok(DefaultCredentials::AuthorizedUser(_)) => {}
+            _ => panic!("Expected authorized user credentials"),
+        }
+        fs::remove_dir_all(config_dir).unwrap();
+
+        let var = |name: &str| match name {
+            "HOME" | "APPDATA" => Some("/home/user".into()),
+            _ => None,
+        };
+        let config_dir = if cfg!(windows) {
+            Path::new("/home/user").join("gcloud")
+        } else {
+            Path::new("/home/user").join(".config").join("gcloud")
+        };
+        assert_eq!(well_known_file(var), Some(config_dir.join(WELL_KNOWN_FILE)));
+    }
+}
@@ -38,6 +38,7 @@ pub struct DeviceFlow<C> {
     error: Option<PollError>,
     application_secret: ApplicationSecret,
     device_code_url: String,
+    login_hint: Option<String>,
 }
 
 impl<C> Flow for DeviceFlow<C> {
@@ -61,9 +62,17 @@ where
             device_code_url: device_code_url.as_ref().to_string(),
             state: None,
             error: None,
+            login_hint: None,
         }
     }
 
+    /// Asks the authorization server to preselect the given account (usually an email
+    /// address) when the user enters the code.
+    pub fn with_login_hint<S: AsRef<str>>(mut self, login_hint: S) -> DeviceFlow<C> {
+        self.login_hint = Some(login_hint.as_ref().to_string());
+        self
+    }
+
     /// The first step involves asking the server for a code that the user
     /// can type into a field at a specified URL. It is called only once, assuming
     /// there was no connection error. Otherwise, it may be called again until
@@ -90,19 +99,22 @@ where
 
         // note: cloned() shouldn't be needed, see issue
         // https://github.com/servo/rust-url/issues/81
-        let req = form_urlencoded::Serializer::new(String::new())
-            .extend_pairs(&[
-                ("client_id", &self.application_secret.client_id),
-                (
-                    "scope",
-                    &scopes
-                        .into_iter()
-                        .map(|s| s.as_ref())
-                        .intersperse(" ")
-                        .collect::<String>(),
-                ),
-            ])
-            .finish();
+        let mut req = form_urlencoded::Serializer::new(String::new());
+        req.extend_pairs(&[
+            ("client_id", &self.application_secret.client_id),
+            (
+                "scope",
+                &scopes
+                    .into_iter()
+                    .map(|s| s.as_ref())
+                    .intersperse(" ")
+                    .collect::<String>(),
+            ),
+        ]);
+        if let Some(ref hint) = self.login_hint {
+            req.append_pair("login_hint", hint);
+        }
+        let req = req.finish();
 
         // note: works around bug in rustlang
         // https://github.com/rust-lang/rust/issues/22252
@@ -300,6 +312,15 @@ pub mod tests {
         }
     }
 
+    impl MockGoogleAuth {
+        /// Like the default mock, but answers the successful poll with the given token response.
+        pub fn with_token_response(body: &str) -> MockGoogleAuth {
+            let mut c = <MockGoogleAuth as Default>::default();
+            c.0.content[2] = format!("HTTP/1.1 200 OK\r\nServer: BOGUS\r\n\r\n{}", body);
+            c
+        }
+    }
+
     impl hyper::net::NetworkConnector for MockGoogleAuth {
         type Stream = MockStream;
 
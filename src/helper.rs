@@ -7,13 +7,18 @@
 //
 // Refer to the project root for licensing information.
 
+use serde::de::DeserializeOwned;
 use serde_json;
 
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::io::{self, Read};
 use std::path::Path;
 
-use crate::service_account::ServiceAccountKey;
+use crate::authorized_user::{AuthorizedUserKey, GOOGLE_TOKEN_URI};
+use crate::service_account::{ExternalAccountKey, ServiceAccountKey};
 use crate::types::{ApplicationSecret, ConsoleApplicationSecret};
 
 /// Read an application secret from a file.
@@ -51,15 +56,310 @@ pub fn parse_application_secret<S: AsRef<str>>(secret: S) -> io::Result<Applicat
     }
 }
 
-/// Read a service account key from a JSON file. You can download the JSON keys from the Google
-/// Cloud Console or the respective console of your service provider.
-pub fn service_account_key_from_file<S: AsRef<Path>>(path: S) -> io::Result<ServiceAccountKey> {
+/// Reads the JSON file at `path` and decodes it as `T`.
+pub(crate) fn read_json_file<T: DeserializeOwned, S: AsRef<Path>>(path: S) -> io::Result<T> {
     let mut key = String::new();
     let mut file = fs::OpenOptions::new().read(true).open(path)?;
     file.read_to_string(&mut key)?;
 
-    match serde_json::from_str(&key) {
+    parse_json(&key)
+}
+
+/// Decodes `json` as `T`, failing with an error of kind `InvalidData`.
+pub(crate) fn parse_json<T: DeserializeOwned>(json: &str) -> io::Result<T> {
+    match serde_json::from_str(json) {
         Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}", e))),
         Ok(decoded) => Ok(decoded),
     }
 }
+
+/// Read a service account key from a JSON file. You can download the JSON keys from the Google
+/// Cloud Console or the respective console of your service provider.
+pub fn service_account_key_from_file<S: AsRef<Path>>(path: S) -> io::Result<ServiceAccountKey> {
+    read_json_file(path)
+}
+
+/// The password of PKCS#12 keys issued by Google.
+pub const PKCS12_DEFAULT_PASSWORD: &str = "notasecret";
+
+/// Returned by the functions loading a `ServiceAccountKey` from strings, readers, environment
+/// variables and PKCS#12 archives.
+#[derive(Debug)]
+pub enum KeyError {
+    /// Reading the key failed.
+    Io(io::Error),
+    /// The environment variable isn't set or not valid unicode.
+    Env(String, env::VarError),
+    /// The key isn't valid JSON or base64-encoded JSON.
+    Json(serde_json::Error),
+    /// The key isn't valid base64.
+    Base64(base64::DecodeError),
+    /// The `type` of the key isn't `service_account`, e.g. `authorized_user`.
+    WrongType(Option<String>),
+    /// A field required to obtain tokens is missing.
+    MissingField(&'static str),
+    /// The PKCS#12 archive can't be decoded with the password, or contains no private key.
+    Pkcs12(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            KeyError::Io(ref err) => write!(f, "Reading service account key failed: {}", err),
+            KeyError::Env(ref var, ref err) => write!(f, "{}: {}", var, err),
+            KeyError::Json(ref err) => write!(f, "Bad service account key: {}", err),
+            KeyError::Base64(ref err) => write!(f, "Bad base64 service account key: {}", err),
+            KeyError::WrongType(Some(ref key_type)) => {
+                write!(f, "Expected a service_account key, got {}", key_type)
+            }
+            KeyError::WrongType(None) => write!(f, "Service account key lacks type"),
+            KeyError::MissingField(field) => write!(f, "Service account key lacks {}", field),
+            KeyError::Pkcs12(ref err) => write!(f, "Bad PKCS#12 key: {}", err),
+        }
+    }
+}
+
+impl Error for KeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            KeyError::Io(ref err) => Some(err),
+            KeyError::Env(_, ref err) => Some(err),
+            KeyError::Json(ref err) => Some(err),
+            KeyError::Base64(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for KeyError {
+    fn from(err: io::Error) -> KeyError {
+        KeyError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KeyError {
+    fn from(err: serde_json::Error) -> KeyError {
+        KeyError::Json(err)
+    }
+}
+
+impl From<base64::DecodeError> for KeyError {
+    fn from(err: base64::DecodeError) -> KeyError {
+        KeyError::Base64(err)
+    }
+}
+
+/// Checks that `key` is a service account key with the fields needed to obtain tokens. A
+/// missing `token_uri` is set to Google's token endpoint.
+fn validate_service_account_key(mut key: ServiceAccountKey) -> Result<ServiceAccountKey, KeyError> {
+    if key.key_type.as_deref() != Some("service_account") {
+        return Err(KeyError::WrongType(key.key_type));
+    }
+    if key.client_email.is_none() {
+        return Err(KeyError::MissingField("client_email"));
+    }
+    if key.private_key.is_none() {
+        return Err(KeyError::MissingField("private_key"));
+    }
+    if key.token_uri.is_none() {
+        key.token_uri = Some(GOOGLE_TOKEN_URI.to_string());
+    }
+    Ok(key)
+}
+
+/// Parse a service account key from its JSON representation, checking that it is a
+/// `service_account` key with a client email and private key.
+pub fn service_account_key_from_str<S: AsRef<str>>(key: S) -> Result<ServiceAccountKey, KeyError> {
+    validate_service_account_key(serde_json::from_str(key.as_ref())?)
+}
+
+/// Read a service account key in JSON format from `reader`, like
+/// `service_account_key_from_str()`.
+pub fn service_account_key_from_reader<R: Read>(
+    mut reader: R,
+) -> Result<ServiceAccountKey, KeyError> {
+    let mut key = String::new();
+    reader.read_to_string(&mut key)?;
+    service_account_key_from_str(key)
+}
+
+/// Read a service account key from the environment variable `var`, which contains either the
+/// JSON key itself or its base64 encoding, like secrets injected by Kubernetes often do.
+pub fn service_account_key_from_env<S: AsRef<str>>(var: S) -> Result<ServiceAccountKey, KeyError> {
+    let value = env::var(var.as_ref()).map_err(|e| KeyError::Env(var.as_ref().to_string(), e))?;
+    service_account_key_from_env_value(&value)
+}
+
+/// Parses the value of the environment variable of `service_account_key_from_env()`.
+fn service_account_key_from_env_value(value: &str) -> Result<ServiceAccountKey, KeyError> {
+    let value = value.trim();
+    if value.starts_with('{') {
+        service_account_key_from_str(value)
+    } else {
+        let decoded = base64::decode(value)?;
+        validate_service_account_key(serde_json::from_slice(&decoded)?)
+    }
+}
+
+/// Read a legacy service account key from a PKCS#12 (`.p12`) archive, which is protected with
+/// `password`, usually `PKCS12_DEFAULT_PASSWORD`. As the archive only contains the private key,
+/// the email address of the service account needs to be given.
+///
+/// This requires the `openssl` feature; without it, this always fails with `KeyError::Pkcs12`.
+#[cfg(feature = "openssl")]
+pub fn service_account_key_from_pkcs12<S: AsRef<str>>(
+    der: &[u8],
+    password: &str,
+    client_email: S,
+) -> Result<ServiceAccountKey, KeyError> {
+    let pkcs12_error = |e: openssl::error::ErrorStack| KeyError::Pkcs12(e.to_string());
+    let parsed = openssl::pkcs12::Pkcs12::from_der(der)
+        .and_then(|pkcs12| pkcs12.parse2(password))
+        .map_err(pkcs12_error)?;
+    let pkey = parsed
+        .pkey
+        .ok_or_else(|| KeyError::Pkcs12("No private key".to_string()))?;
+    let pem = pkey.private_key_to_pem_pkcs8().map_err(pkcs12_error)?;
+
+    Ok(ServiceAccountKey {
+        key_type: Some("service_account".to_string()),
+        project_id: None,
+        private_key_id: None,
+        private_key: Some(String::from_utf8_lossy(&pem).into_owned()),
+        client_email: Some(client_email.as_ref().to_string()),
+        client_id: None,
+        auth_uri: None,
+        token_uri: Some(GOOGLE_TOKEN_URI.to_string()),
+        auth_provier_x509_cert_url: None,
+        client_x509_cert_url: None,
+    })
+}
+
+/// Read a legacy service account key from a PKCS#12 (`.p12`) archive. PKCS#12 archives can
+/// only be decoded with openssl, so without the `openssl` feature this always fails with
+/// `KeyError::Pkcs12`.
+#[cfg(not(feature = "openssl"))]
+pub fn service_account_key_from_pkcs12<S: AsRef<str>>(
+    _der: &[u8],
+    _password: &str,
+    _client_email: S,
+) -> Result<ServiceAccountKey, KeyError> {
+    Err(KeyError::Pkcs12(
+        "PKCS#12 keys require the `openssl` feature".to_string(),
+    ))
+}
+
+/// Read `authorized_user` credentials from a JSON file, as written by `gcloud auth
+/// application-default login`.
+pub fn authorized_user_from_file<S: AsRef<Path>>(path: S) -> io::Result<AuthorizedUserKey> {
+    read_json_file(path)
+}
+
+/// Read `external_account` credentials from a JSON file, as used for workload identity
+/// federation.
+pub fn external_account_key_from_file<S: AsRef<Path>>(path: S) -> io::Result<ExternalAccountKey> {
+    read_json_file(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_PRIVATE_KEY_PATH: &str = "examples/Sanguine-69411a0c0eea.json";
+
+    fn test_key_json() -> String {
+        fs::read_to_string(TEST_PRIVATE_KEY_PATH).unwrap()
+    }
+
+    #[test]
+    fn service_account_key_from_strings() {
+        let key = service_account_key_from_str(test_key_json()).unwrap();
+        assert_eq!(
+            key.client_email.as_ref().unwrap(),
+            "oauth2-public-test@sanguine-rhythm-105020.iam.gserviceaccount.com"
+        );
+        let from_reader = service_account_key_from_reader(test_key_json().as_bytes()).unwrap();
+        assert_eq!(from_reader.private_key, key.private_key);
+
+        let mut json: serde_json::Value = serde_json::from_str(&test_key_json()).unwrap();
+        json["type"] = "authorized_user".into();
+        match service_account_key_from_str(json.to_string()) {
+            Err(KeyError::WrongType(Some(ref t))) if t == "authorized_user" => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        json["type"] = "service_account".into();
+        json.as_object_mut().unwrap().remove("token_uri");
+        let key = service_account_key_from_str(json.to_string()).unwrap();
+        assert_eq!(
+            key.token_uri.unwrap(),
+            "https://oauth2.googleapis.com/token"
+        );
+        json.as_object_mut().unwrap().remove("private_key");
+        match service_account_key_from_str(json.to_string()) {
+            Err(KeyError::MissingField("private_key")) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match service_account_key_from_str("{") {
+            Err(KeyError::Json(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn service_account_key_from_env_var() {
+        // Setting variables would race with other tests reading the environment, so the
+        // values are passed in directly.
+        let raw = service_account_key_from_env_value(&test_key_json()).unwrap();
+        let b64 =
+            service_account_key_from_env_value(&format!("{}\n", base64::encode(&test_key_json())))
+                .unwrap();
+        assert_eq!(raw.private_key_id, b64.private_key_id);
+
+        match service_account_key_from_env("YUP_OAUTH2_TEST_KEY_UNSET") {
+            Err(KeyError::Env(ref var, env::VarError::NotPresent)) => {
+                assert_eq!(var, "YUP_OAUTH2_TEST_KEY_UNSET")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn service_account_key_from_p12() {
+        use openssl::pkey::PKey;
+
+        let key = service_account_key_from_str(test_key_json()).unwrap();
+        let pkey = PKey::private_key_from_pem(
+            key.private_key
+                .as_ref()
+                .unwrap()
+                .replace("\\n", "\n")
+                .as_bytes(),
+        )
+        .unwrap();
+        let der = openssl::pkcs12::Pkcs12::builder()
+            .name("privatekey")
+            .pkey(&pkey)
+            .build2(PKCS12_DEFAULT_PASSWORD)
+            .unwrap()
+            .to_der()
+            .unwrap();
+
+        let p12 = service_account_key_from_pkcs12(
+            &der,
+            PKCS12_DEFAULT_PASSWORD,
+            key.client_email.as_ref().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(p12.client_email, key.client_email);
+        assert_eq!(
+            p12.sign_blob(b"data").unwrap(),
+            key.sign_blob(b"data").unwrap()
+        );
+
+        match service_account_key_from_pkcs12(&der, "wrong", "sa@example.com") {
+            Err(KeyError::Pkcs12(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
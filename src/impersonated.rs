@@ -0,0 +1,258 @@
+//! This module provides a token source (`GetToken`) impersonating a service account: tokens
+//! of another token source, e.g. an `Authenticator` or a `ServiceAccountAccess`, are used to
+//! mint tokens for the target service account with the IAM Credentials API. The principal of
+//! the source tokens needs the "Service Account Token Creator" role on the target.
+//!
+//! Resources:
+//! - [Creating short-lived service account
+//!   credentials](https://cloud.google.com/iam/docs/creating-short-lived-service-account-credentials)
+
+use std::borrow::BorrowMut;
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::authenticator::GetToken;
+use crate::storage::{hash_scopes, MemoryStorage, TokenStorage};
+use crate::types::{Scheme, StringError, Token, TokenType};
+
+use hyper::header;
+
+/// The base URL of the IAM Credentials API.
+pub const IAM_CREDENTIALS_URL: &str = "https://iamcredentials.googleapis.com";
+/// The scope required to call the IAM Credentials API.
+pub(crate) const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// Calls the `generateAccessToken` method at `url`, authenticated with `token`.
+///
+/// `delegates` is the chain of service accounts through which the permission to impersonate
+/// is delegated, and `lifetime` the requested lifetime of the token, which defaults to one
+/// hour.
+pub(crate) fn generate_access_token(
+    client: &mut hyper::Client,
+    url: &str,
+    token: &Token,
+    scopes: &[&str],
+    delegates: &[String],
+    lifetime: Option<Duration>,
+) -> Result<Token, Box<dyn Error>> {
+    #[derive(Serialize)]
+    struct GenerateAccessTokenRequest<'a> {
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        delegates: Vec<String>,
+        scope: &'a [&'a str],
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lifetime: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct GenerateAccessTokenResponse {
+        access_token: String,
+        expire_time: String,
+    }
+
+    let body = serde_json::to_string(&GenerateAccessTokenRequest {
+        delegates: delegates
+            .iter()
+            .map(|d| format!("projects/-/serviceAccounts/{}", d))
+            .collect(),
+        scope: scopes,
+        lifetime: lifetime.map(|l| format!("{}s", l.as_secs())),
+    })?;
+    let mut result = client
+        .post(url)
+        .body(&body)
+        .header(header::ContentType::json())
+        .header(header::Authorization(Scheme {
+            token_type: TokenType::Bearer,
+            access_token: token.access_token.clone(),
+        }))
+        .send()?;
+    let mut response = String::new();
+    result.read_to_string(&mut response)?;
+    if !result.status.is_success() {
+        return Err(Box::new(StringError::new(
+            format!(
+                "Service account impersonation failed with {}",
+                result.status
+            ),
+            Some(&response),
+        )));
+    }
+
+    let generated: GenerateAccessTokenResponse = serde_json::from_str(&response)?;
+    let expiry = chrono::DateTime::parse_from_rfc3339(&generated.expire_time)?;
+    Ok(Token {
+        access_token: generated.access_token,
+        refresh_token: String::new(),
+        token_type: TokenType::Bearer.as_ref().to_string(),
+        expires_in: None,
+        expires_in_timestamp: Some(expiry.timestamp()),
+        scope: Some(scopes.join(" ")),
+        id_token: None,
+    })
+}
+
+/// A token source (`GetToken`) yielding tokens for a target service account, minted with the
+/// tokens of another token source. Tokens are cached per set of scopes.
+pub struct ImpersonatedAccess<G, C> {
+    source: G,
+    client: C,
+    target: String,
+    delegates: Vec<String>,
+    lifetime: Option<Duration>,
+    endpoint: String,
+    cache: MemoryStorage,
+}
+
+impl<G, C> ImpersonatedAccess<G, C>
+where
+    G: GetToken,
+    C: BorrowMut<hyper::Client>,
+{
+    /// Returns a token source impersonating the service account with the email address
+    /// `target`, using tokens of `source`.
+    pub fn new<S: AsRef<str>>(source: G, client: C, target: S) -> ImpersonatedAccess<G, C> {
+        ImpersonatedAccess {
+            source,
+            client,
+            target: target.as_ref().to_string(),
+            delegates: Vec::new(),
+            lifetime: None,
+            endpoint: IAM_CREDENTIALS_URL.to_string(),
+            cache: MemoryStorage::default(),
+        }
+    }
+
+    /// Sets the chain of service accounts, identified by their email addresses, through which
+    /// the source principal is allowed to impersonate the target. The last delegate must be
+    /// allowed to impersonate the target.
+    pub fn with_delegates<I, T>(mut self, delegates: I) -> ImpersonatedAccess<G, C>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        self.delegates = delegates
+            .into_iter()
+            .map(|d| d.as_ref().to_string())
+            .collect();
+        self
+    }
+
+    /// Requests tokens with the given lifetime instead of the default of one hour. Lifetimes
+    /// over one hour need to be allowed by an organization policy.
+    pub fn with_lifetime(mut self, lifetime: Duration) -> ImpersonatedAccess<G, C> {
+        self.lifetime = Some(lifetime);
+        self
+    }
+
+    /// Uses the IAM Credentials API at `endpoint` instead of `IAM_CREDENTIALS_URL`.
+    pub fn with_endpoint<S: AsRef<str>>(mut self, endpoint: S) -> ImpersonatedAccess<G, C> {
+        self.endpoint = endpoint.as_ref().trim_end_matches('/').to_string();
+        self
+    }
+}
+
+impl<G, C> GetToken for ImpersonatedAccess<G, C>
+where
+    G: GetToken,
+    C: BorrowMut<hyper::Client>,
+{
+    fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        let (hash, scps) = hash_scopes(scopes);
+
+        if let Some(token) = self.cache.get(hash, &scps)? {
+            if !token.expired() {
+                return Ok(token);
+            }
+        }
+
+        let source = self.source.token(&[CLOUD_PLATFORM_SCOPE])?;
+        let url = format!(
+            "{}/v1/projects/-/serviceAccounts/{}:generateAccessToken",
+            self.endpoint, self.target
+        );
+        let token = generate_access_token(
+            self.client.borrow_mut(),
+            &url,
+            &source,
+            &scps,
+            &self.delegates,
+            self.lifetime,
+        )?;
+        let _ = self.cache.set(hash, &scps, Some(token.clone()));
+
+        Ok(token)
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::tests::MockConnector;
+
+    struct StaticToken;
+
+    impl GetToken for StaticToken {
+        fn token<'b, I, T>(&mut self, _: I) -> Result<Token, Box<dyn Error>>
+        where
+            T: AsRef<str> + Ord + 'b,
+            I: IntoIterator<Item = &'b T>,
+        {
+            Ok(Token {
+                access_token: "source".to_string(),
+                refresh_token: String::new(),
+                token_type: "Bearer".to_string(),
+                expires_in: None,
+                expires_in_timestamp: Some(4102444800),
+                scope: None,
+                id_token: None,
+            })
+        }
+
+        fn api_key(&mut self) -> Option<String> {
+            None
+        }
+    }
+
+    const IAM_CREDENTIALS_RESPONSES: [&str; 2] = [
+        "HTTP/1.1 200 OK\r\n\
+         \r\n\
+         {\"accessToken\":\"ya29.impersonated\",\"expireTime\":\"2099-01-01T00:00:00Z\"}",
+        "HTTP/1.1 403 Forbidden\r\n\
+         \r\n\
+         {\"error\":{\"code\":403,\"status\":\"PERMISSION_DENIED\"}}",
+    ];
+
+    #[test]
+    fn impersonate_service_account() {
+        let mut acc = ImpersonatedAccess::new(
+            StaticToken,
+            hyper::Client::with_connector(MockConnector::new(&IAM_CREDENTIALS_RESPONSES)),
+            "target@project.iam.gserviceaccount.com",
+        )
+        .with_delegates(["delegate@project.iam.gserviceaccount.com"])
+        .with_lifetime(Duration::from_secs(600))
+        .with_endpoint("http://127.0.0.1:8080/");
+
+        let scopes = ["https://www.googleapis.com/auth/pubsub"];
+        let token = acc.token(&scopes).unwrap();
+        assert_eq!(token.access_token, "ya29.impersonated");
+        assert_eq!(token.expires_in_timestamp, Some(4070908800));
+        assert_eq!(acc.token(&scopes).unwrap(), token);
+
+        let err = acc
+            .token(&["https://www.googleapis.com/auth/devstorage.read_only"])
+            .unwrap_err();
+        assert!(err.to_string().contains("403"));
+    }
+}
@@ -15,58 +15,124 @@ use std::sync::Mutex;
 
 use hyper;
 use hyper::{client, header, server, status, uri};
-use serde_json::error;
 use url::form_urlencoded;
-use url::percent_encoding::{percent_encode, QUERY_ENCODE_SET};
+use url::Url;
 
 use crate::authenticator_delegate::AuthenticatorDelegate;
 use crate::types::{ApplicationSecret, Token};
 
 const OOB_REDIRECT_URI: &'static str = "urn:ietf:wg:oauth:2.0:oob";
 
+/// Parameters set by the flows themselves, which `AuthParams` can't override.
+const RESERVED_PARAMS: &[&str] = &["scope", "redirect_uri", "response_type", "client_id"];
+
+/// Additional parameters of authorization requests, beyond the scopes, client ID and redirect
+/// URI. See [Google's
+/// documentation](https://developers.google.com/identity/protocols/OAuth2WebServer#creatingclient)
+/// for the parameters it supports.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AuthParams {
+    params: Vec<(String, String)>,
+}
+
+impl AuthParams {
+    /// Asks for offline access with `access_type=offline` and `prompt=consent`, so that the
+    /// server issues a refresh token even if the user authorized the application before.
+    pub fn offline(self) -> AuthParams {
+        self.with_param("access_type", "offline")
+            .with_param("prompt", "consent")
+    }
+
+    /// Sets `prompt`, e.g. to `consent` or `select_account`.
+    pub fn with_prompt<S: AsRef<str>>(self, prompt: S) -> AuthParams {
+        self.with_param("prompt", prompt)
+    }
+
+    /// Restricts sign-in to accounts of the G Suite domain `hd`.
+    pub fn with_hosted_domain<S: AsRef<str>>(self, hd: S) -> AuthParams {
+        self.with_param("hd", hd)
+    }
+
+    /// Sets `include_granted_scopes=true`, so that the new grant includes the scopes the user
+    /// granted the application before.
+    pub fn with_include_granted_scopes(self) -> AuthParams {
+        self.with_param("include_granted_scopes", "true")
+    }
+
+    /// Sets `nonce`, which the server includes in ID tokens.
+    pub fn with_nonce<S: AsRef<str>>(self, nonce: S) -> AuthParams {
+        self.with_param("nonce", nonce)
+    }
+
+    /// Sets the parameter `name` to `value`, replacing an earlier value. The parameters set by
+    /// the flows themselves (`scope`, `redirect_uri`, `response_type` and `client_id`) are
+    /// ignored when building the request, and so is `login_hint` if the flow has one.
+    pub fn with_param<S: AsRef<str>, T: AsRef<str>>(mut self, name: S, value: T) -> AuthParams {
+        let name = name.as_ref();
+        self.params.retain(|(n, _)| n != name);
+        self.params
+            .push((name.to_string(), value.as_ref().to_string()));
+        self
+    }
+
+    /// Returns the value of the parameter `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
 /// Assembles a URL to request an authorization token (with user interaction).
 /// Note that the redirect_uri here has to be either None or some variation of
 /// http://localhost:{port}, or the authorization won't work (error "redirect_uri_mismatch")
-fn build_authentication_request_url<'a, T, I>(
+pub(crate) fn build_authentication_request_url<'a, T, I>(
     auth_uri: &str,
     client_id: &str,
     scopes: I,
     redirect_uri: Option<String>,
-) -> String
+    login_hint: Option<&str>,
+    params: &AuthParams,
+) -> Result<String, url::ParseError>
 where
     T: AsRef<str> + 'a,
     I: IntoIterator<Item = &'a T>,
 {
-    let mut url = String::new();
-    let mut scopes_string = scopes.into_iter().fold(String::new(), |mut acc, sc| {
-        acc.push_str(sc.as_ref());
-        acc.push_str(" ");
-        acc
-    });
-    // Remove last space
-    scopes_string.pop();
-
-    url.push_str(auth_uri);
-    vec![
-        format!("?scope={}", scopes_string),
-        format!(
-            "&redirect_uri={}",
-            redirect_uri.unwrap_or(OOB_REDIRECT_URI.to_string())
-        ),
-        format!("&response_type=code"),
-        format!("&client_id={}", client_id),
-    ]
-    .into_iter()
-    .fold(url, |mut u, param| {
-        u.push_str(&percent_encode(param.as_ref(), QUERY_ENCODE_SET).to_string());
-        u
-    })
+    let scopes: Vec<&str> = scopes.into_iter().map(|s| s.as_ref()).collect();
+
+    let mut url = Url::parse(auth_uri)?;
+    {
+        let mut query = url.query_pairs_mut();
+        query
+            .append_pair("scope", &scopes.join(" "))
+            .append_pair(
+                "redirect_uri",
+                &redirect_uri.unwrap_or(OOB_REDIRECT_URI.to_string()),
+            )
+            .append_pair("response_type", "code")
+            .append_pair("client_id", client_id);
+        if let Some(hint) = login_hint {
+            query.append_pair("login_hint", hint);
+        }
+        for (name, value) in &params.params {
+            let set_by_flow = RESERVED_PARAMS.contains(&name.as_str())
+                || (name == "login_hint" && login_hint.is_some());
+            if !set_by_flow {
+                query.append_pair(name, value);
+            }
+        }
+    }
+    Ok(url.into_string())
 }
 
 pub struct InstalledFlow<C> {
     client: C,
     server: Option<server::Listening>,
     port: Option<u32>,
+    login_hint: Option<String>,
+    redirect_uri: Option<String>,
+    auth_params: AuthParams,
 
     auth_code_rcv: Option<Receiver<String>>,
 }
@@ -94,6 +160,9 @@ where
             client: client,
             server: None,
             port: None,
+            login_hint: None,
+            redirect_uri: None,
+            auth_params: AuthParams::default(),
             auth_code_rcv: None,
         };
         match method {
@@ -117,6 +186,9 @@ where
                                 client: default.client,
                                 server: Some(listening),
                                 port: Some(port),
+                                login_hint: None,
+                                redirect_uri: None,
+                                auth_params: AuthParams::default(),
                                 auth_code_rcv: Some(rx),
                             },
                         }
@@ -126,6 +198,28 @@ where
         }
     }
 
+    /// Asks the authorization server to preselect the given account (usually an email
+    /// address) when the user is asked to sign in.
+    pub fn with_login_hint<S: AsRef<str>>(mut self, login_hint: S) -> InstalledFlow<C> {
+        self.login_hint = Some(login_hint.as_ref().to_string());
+        self
+    }
+
+    /// Uses `redirect_uri` instead of the one returned by `AuthenticatorDelegate::redirect_uri()`
+    /// or the default one. With `InstalledFlowReturnMethod::HTTPRedirect`, it has to point to
+    /// the local server.
+    pub fn with_redirect_uri<S: AsRef<str>>(mut self, redirect_uri: S) -> InstalledFlow<C> {
+        self.redirect_uri = Some(redirect_uri.as_ref().to_string());
+        self
+    }
+
+    /// Adds `params` to the authorization request, e.g. `AuthParams::default().offline()` to
+    /// reliably receive a refresh token.
+    pub fn with_auth_params(mut self, params: AuthParams) -> InstalledFlow<C> {
+        self.auth_params = params;
+        self
+    }
+
     /// Handles the token request flow; it consists of the following steps:
     /// . Obtain a auhorization code with user cooperation or internal redirect.
     /// . Obtain a token and refresh token using that code.
@@ -143,32 +237,21 @@ where
         S: Iterator<Item = &'a T>,
     {
         let authcode = self.get_authorization_code(auth_delegate, &appsecret, scopes)?;
-        let tokens = self.request_token(&appsecret, &authcode, auth_delegate.redirect_uri())?;
-
-        // Successful response
-        if tokens.access_token.is_some() {
-            let mut token = Token {
-                access_token: tokens.access_token.unwrap(),
-                refresh_token: tokens.refresh_token.unwrap(),
-                token_type: tokens.token_type.unwrap(),
-                expires_in: tokens.expires_in,
-                expires_in_timestamp: None,
-            };
-
-            token.set_expiry_absolute();
-            Result::Ok(token)
-        } else {
-            let err = io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Token API error: {} {}",
-                    tokens.error.unwrap_or("<unknown err>".to_string()),
-                    tokens.error_description.unwrap_or("".to_string())
-                )
-                .as_str(),
-            );
-            Result::Err(Box::new(err))
-        }
+        let redirect_uri = self
+            .redirect_uri
+            .clone()
+            .or_else(|| auth_delegate.redirect_uri())
+            .unwrap_or_else(|| match self.port {
+                None => OOB_REDIRECT_URI.to_string(),
+                Some(p) => format!("http://localhost:{}", p),
+            });
+        request_token(
+            self.client.borrow_mut(),
+            appsecret,
+            &authcode,
+            &redirect_uri,
+            None,
+        )
     }
 
     /// Obtains an authorization code either interactively or via HTTP redirect (see
@@ -183,14 +266,29 @@ where
         T: AsRef<str> + 'a,
         S: Iterator<Item = &'a T>,
     {
-        let result: Result<String, Box<Error>> = match self.server {
-            None => {
-                let url = build_authentication_request_url(
-                    &appsecret.auth_uri,
-                    &appsecret.client_id,
-                    scopes,
-                    auth_delegate.redirect_uri(),
-                );
+        let redirect_uri = self
+            .redirect_uri
+            .clone()
+            .or_else(|| auth_delegate.redirect_uri());
+        let redirect_uri = match self.server {
+            None => redirect_uri,
+            // The redirect URI must be this very localhost URL, otherwise Google refuses
+            // authorization.
+            Some(_) => redirect_uri
+                .or_else(|| Some(format!("http://localhost:{}", self.port.unwrap_or(8080)))),
+        };
+        let url = build_authentication_request_url(
+            &appsecret.auth_uri,
+            &appsecret.client_id,
+            scopes,
+            redirect_uri,
+            self.login_hint.as_deref(),
+            &self.auth_params,
+        );
+
+        let result: Result<String, Box<Error>> = match (url, &self.server) {
+            (Err(err), _) => Result::Err(Box::new(err)),
+            (Ok(url), None) => {
                 match auth_delegate.present_user_url(&url, true /* need_code */) {
                     None => Result::Err(Box::new(io::Error::new(
                         io::ErrorKind::UnexpectedEof,
@@ -208,17 +306,7 @@ where
                     }
                 }
             }
-            Some(_) => {
-                // The redirect URI must be this very localhost URL, otherwise Google refuses
-                // authorization.
-                let url = build_authentication_request_url(
-                    &appsecret.auth_uri,
-                    &appsecret.client_id,
-                    scopes,
-                    auth_delegate.redirect_uri().or_else(|| {
-                        Some(format!("http://localhost:{}", self.port.unwrap_or(8080)))
-                    }),
-                );
+            (Ok(url), Some(_)) => {
                 auth_delegate.present_user_url(&url, false /* need_code */);
 
                 match self.auth_code_rcv.as_ref().unwrap().recv() {
@@ -230,59 +318,80 @@ where
         self.server.as_mut().map(|l| l.close()).is_some();
         result
     }
+}
 
-    /// Sends the authorization code to the provider in order to obtain access and refresh tokens.
-    fn request_token(
-        &mut self,
-        appsecret: &ApplicationSecret,
-        authcode: &str,
-        custom_redirect_uri: Option<String>,
-    ) -> Result<JSONTokenResponse, Box<Error>> {
-        let redirect_uri = custom_redirect_uri.unwrap_or_else(|| match self.port {
-            None => OOB_REDIRECT_URI.to_string(),
-            Some(p) => format!("http://localhost:{}", p),
-        });
-
-        let body = form_urlencoded::Serializer::new(String::new())
-            .extend_pairs(vec![
-                ("code".to_string(), authcode.to_string()),
-                ("client_id".to_string(), appsecret.client_id.clone()),
-                ("client_secret".to_string(), appsecret.client_secret.clone()),
-                ("redirect_uri".to_string(), redirect_uri),
-                ("grant_type".to_string(), "authorization_code".to_string()),
-            ])
-            .finish();
-
-        let result: Result<client::Response, hyper::Error> = self
-            .client
-            .borrow_mut()
-            .post(&appsecret.token_uri)
-            .body(&body)
-            .header(header::ContentType(
-                "application/x-www-form-urlencoded".parse().unwrap(),
-            ))
-            .send();
-
-        let mut resp = String::new();
-
-        match result {
-            Result::Err(e) => return Result::Err(Box::new(e)),
-            Result::Ok(mut response) => {
-                let result = response.read_to_string(&mut resp);
-
-                match result {
-                    Result::Err(e) => return Result::Err(Box::new(e)),
-                    Result::Ok(_) => (),
-                }
+/// Sends the authorization code `authcode`, which was issued for `redirect_uri`, to the provider
+/// in order to obtain access and refresh tokens. `code_verifier` is the PKCE verifier of the
+/// authorization request, if it had one.
+pub(crate) fn request_token(
+    client: &mut hyper::Client,
+    appsecret: &ApplicationSecret,
+    authcode: &str,
+    redirect_uri: &str,
+    code_verifier: Option<&str>,
+) -> Result<Token, Box<Error>> {
+    let body = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(vec![
+            ("code".to_string(), authcode.to_string()),
+            ("client_id".to_string(), appsecret.client_id.clone()),
+            ("client_secret".to_string(), appsecret.client_secret.clone()),
+            ("redirect_uri".to_string(), redirect_uri.to_string()),
+            ("grant_type".to_string(), "authorization_code".to_string()),
+        ])
+        .extend_pairs(code_verifier.map(|v| ("code_verifier", v)))
+        .finish();
+
+    let result: Result<client::Response, hyper::Error> = client
+        .post(&appsecret.token_uri)
+        .body(&body)
+        .header(header::ContentType(
+            "application/x-www-form-urlencoded".parse().unwrap(),
+        ))
+        .send();
+
+    let mut resp = String::new();
+
+    match result {
+        Result::Err(e) => return Result::Err(Box::new(e)),
+        Result::Ok(mut response) => {
+            let result = response.read_to_string(&mut resp);
+
+            match result {
+                Result::Err(e) => return Result::Err(Box::new(e)),
+                Result::Ok(_) => (),
             }
         }
+    }
 
-        let token_resp: Result<JSONTokenResponse, error::Error> = serde_json::from_str(&resp);
+    let tokens: JSONTokenResponse = serde_json::from_str(&resp)?;
+
+    // Successful response
+    if tokens.access_token.is_some() {
+        let mut token = Token {
+            access_token: tokens.access_token.unwrap(),
+            // Without offline access, or if the user authorized the application before,
+            // there is no refresh token.
+            refresh_token: tokens.refresh_token.unwrap_or_default(),
+            token_type: tokens.token_type.unwrap(),
+            expires_in: tokens.expires_in,
+            expires_in_timestamp: None,
+            scope: tokens.scope,
+            id_token: tokens.id_token,
+        };
 
-        match token_resp {
-            Result::Err(e) => return Result::Err(Box::new(e)),
-            Result::Ok(tok) => Result::Ok(tok) as Result<JSONTokenResponse, Box<Error>>,
-        }
+        token.set_expiry_absolute();
+        Result::Ok(token)
+    } else {
+        let err = io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "Token API error: {} {}",
+                tokens.error.unwrap_or("<unknown err>".to_string()),
+                tokens.error_description.unwrap_or("".to_string())
+            )
+            .as_str(),
+        );
+        Result::Err(Box::new(err))
     }
 }
 
@@ -292,6 +401,8 @@ struct JSONTokenResponse {
     refresh_token: Option<String>,
     token_type: Option<String>,
     expires_in: Option<i64>,
+    scope: Option<String>,
+    id_token: Option<String>,
 
     error: Option<String>,
     error_description: Option<String>,
@@ -348,8 +459,8 @@ impl InstalledFlowHandler {
 
 #[cfg(test)]
 mod tests {
-    use super::build_authentication_request_url;
     use super::InstalledFlowHandler;
+    use super::{build_authentication_request_url, AuthParams};
 
     use std::sync::mpsc::channel;
     use std::sync::Mutex;
@@ -359,17 +470,87 @@ mod tests {
     #[test]
     fn test_request_url_builder() {
         assert_eq!(
-            "https://accounts.google.\
-             com/o/oauth2/auth?scope=email%20profile&redirect_uri=urn:ietf:wg:oauth:2.0:\
-             oob&response_type=code&client_id=812741506391-h38jh0j4fv0ce1krdkiq0hfvt6n5amr\
-             f.apps.googleusercontent.com",
+            Ok("https://accounts.google.\
+                com/o/oauth2/auth?scope=email+profile&redirect_uri=urn%3Aietf%3Awg%3Aoauth%3A2.0%3A\
+                oob&response_type=code&client_id=812741506391-h38jh0j4fv0ce1krdkiq0hfvt6n5amr\
+                f.apps.googleusercontent.com"
+                .to_string()),
             build_authentication_request_url(
                 "https://accounts.google.com/o/oauth2/auth",
                 "812741506391-h38jh0j4fv0ce1krdkiq0hfvt6n5am\
                  rf.apps.googleusercontent.com",
                 vec![&"email".to_string(), &"profile".to_string()],
-                None
+                None,
+                None,
+                &AuthParams::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_request_url_builder_login_hint() {
+        assert_eq!(
+            Ok("https://accounts.google.com/o/oauth2/auth?scope=email&redirect_uri=urn%3Aietf%3Awg%3A\
+                oauth%3A2.0%3Aoob&response_type=code&client_id=client&login_hint=me%2Bwork%40example.com"
+                .to_string()),
+            build_authentication_request_url(
+                "https://accounts.google.com/o/oauth2/auth",
+                "client",
+                vec![&"email".to_string()],
+                None,
+                Some("me+work@example.com"),
+                &AuthParams::default()
+            )
+        );
+    }
+
+    #[test]
+    fn test_request_url_builder_params() {
+        let params = AuthParams::default()
+            .offline()
+            .with_hosted_domain("example.com")
+            .with_include_granted_scopes()
+            .with_nonce("n&1")
+            .with_prompt("select_account consent");
+        assert_eq!(params.get("access_type"), Some("offline"));
+        assert_eq!(
+            "https://accounts.google.com/o/oauth2/auth?scope=email&redirect_uri=http%3A%2F%2F\
+             localhost%3A8080&response_type=code&client_id=client&access_type=offline&\
+             hd=example.com&include_granted_scopes=true&nonce=n%261&prompt=select_account+consent",
+            build_authentication_request_url(
+                "https://accounts.google.com/o/oauth2/auth",
+                "client",
+                vec![&"email".to_string()],
+                Some("http://localhost:8080".to_string()),
+                None,
+                &params
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_request_url_builder_reserved_params() {
+        let params = AuthParams::default()
+            .with_param("scope", "https://www.googleapis.com/auth/cloud-platform")
+            .with_param("redirect_uri", "https://attacker.example.com")
+            .with_param("response_type", "token")
+            .with_param("client_id", "other")
+            .with_param("login_hint", "other@example.com")
+            .with_prompt("consent");
+        assert_eq!(
+            "https://accounts.google.com/o/oauth2/auth?scope=email&redirect_uri=urn%3Aietf%3Awg%3A\
+             oauth%3A2.0%3Aoob&response_type=code&client_id=client&login_hint=me%40example.com&\
+             prompt=consent",
+            build_authentication_request_url(
+                "https://accounts.google.com/o/oauth2/auth",
+                "client",
+                vec![&"email".to_string()],
+                None,
+                Some("me@example.com"),
+                &params
             )
+            .unwrap()
         );
     }
 
@@ -19,6 +19,48 @@
 //! for a detailed description of the protocol. This crate implements OAuth for Service Accounts
 //! based on the Google APIs; it may or may not work with other providers.
 //!
+//! Many Google APIs also accept JWTs signed with the service account's key as bearer token;
+//! `SelfSignedJwtAccess` yields such tokens without contacting the token endpoint. Instead of
+//! using a service account's key, `ImpersonatedAccess` can mint tokens for a service
+//! account using the tokens of any other token source. If the private key must not be held by
+//! the application, e.g. because it lives in an HSM, both can sign with a custom `JwtSigner`.
+//!
+//! The key can also sign other things: `ServiceAccountKey::sign_blob()` signs arbitrary bytes,
+//! `ServiceAccountKey::sign_jwt()` signs JWTs with custom claims, and `GcsSignedUrl` creates
+//! signed URLs for Cloud Storage.
+//!
+//! # Cargo features
+//! JWTs are signed with openssl by default. The `ring-signing` feature (also enabled by the older
+//! `no-openssl` feature) signs them with ring instead; build with `--no-default-features
+//! --features ring-signing` to not depend on openssl at all, e.g. for static musl binaries.
+//! One of the two features must be enabled.
+//!
+//! TLS is chosen independently: all flows use the `hyper::Client` they are given, so any HTTPS
+//! connector, e.g. `hyper-native-tls` or `hyper-rustls`, can be used. Only the default client of
+//! `Authenticator::builder()` uses native-tls, through the `native-tls` feature, which is
+//! enabled by default and can be disabled like the `openssl` feature.
+//!
+//! # Application Default Credentials
+//! `default_credentials()` looks up credentials the same way Google's client libraries do:
+//! the file named by `GOOGLE_APPLICATION_CREDENTIALS`, then the file written by `gcloud auth
+//! application-default login`, and finally the metadata server available on Google Compute
+//! Engine (see `MetadataServerAccess`). The resulting `DefaultCredentials` can be used like any
+//! other token source. The `authorized_user` credentials written by gcloud can also be used
+//! directly with `AuthorizedUserAccess`, which never requires user interaction. Workloads running
+//! outside of Google Cloud can use workload identity federation through
+//! `ExternalAccountAccess`.
+//!
+//! # Token exchange
+//! `TokenExchangeFlow` implements the [OAuth 2.0 Token
+//! Exchange](https://tools.ietf.org/html/rfc8693), and `TokenExchangeAccess` uses it to exchange
+//! the tokens of any other token source, e.g. for tokens intended for a different audience.
+//!
+//! # Web Flow Usage
+//! Web applications, which receive the authorization response with their own HTTP server, use
+//! the `WebFlow`. It returns the authorization URL along with the state, PKCE verifier and
+//! nonce to keep in the user's session, and exchanges the code of the response for a token,
+//! which it can save in a `TokenStorage` for that user.
+//!
 //! # Installed Flow Usage
 //! The `InstalledFlow` involves showing a URL to the user (or opening it in a browser)
 //! and then either prompting the user to enter a displayed code, or make the authorizing
@@ -28,11 +70,22 @@
 //! for the redirect method, use `InstalledRedirect`, with the port number to let the
 //! server listen on.
 //!
+//! `Authenticator::builder()` chooses between the device flow and the installed flow based on
+//! the secret, and has setters for the storage, delegate, HTTP client, timeouts, token expiry
+//! skew and retries of failed requests.
+//!
+//! Additional parameters of the authorization request, such as `access_type=offline` or a
+//! hosted domain, are set with `AuthParams`, either on the `InstalledFlow` or through
+//! `AuthenticatorBuilder::with_auth_params()`. With
+//! `AuthenticatorBuilder::with_incremental_authorization()`, scopes requested later are added to
+//! the grant the user gave before, so that a single refresh token covers all of them.
+//!
 //! You can implement your own `AuthenticatorDelegate` in order to customize the flow;
 //! the `InstalledFlow` uses the `present_user_url` method.
 //!
 //! The returned `Token` is stored permanently in the given token storage in order to
-//! authorize future API requests to the same scopes.
+//! authorize future API requests to the same scopes. Applications dealing with several user
+//! accounts can keep their tokens apart by using `Authenticator::token_for()`.
 //!
 //! ```test_harness,no_run
 //! #[macro_use]
@@ -71,7 +124,6 @@ extern crate serde_json;
 extern crate base64;
 extern crate chrono;
 extern crate hyper;
-extern crate hyper_native_tls;
 
 extern crate itertools;
 #[cfg(test)]
@@ -80,26 +132,57 @@ extern crate url;
 #[cfg(test)]
 extern crate yup_hyper_mock;
 
+#[cfg(not(any(feature = "openssl", feature = "ring-signing")))]
+compile_error!(
+    "yup-oauth2 needs a signing backend: enable the `openssl` or `ring-signing` feature"
+);
+
 mod authenticator;
 mod authenticator_delegate;
+mod authorized_user;
+mod default_credentials;
 mod device;
 mod helper;
+mod impersonated;
 mod installed;
+mod metadata;
 mod refresh;
 mod service_account;
+mod signed_url;
 mod storage;
+mod token_exchange;
 mod types;
+mod web;
 
-pub use crate::authenticator::{Authenticator, GetToken, Retry};
+pub use crate::authenticator::{Authenticator, AuthenticatorBuilder, GetToken, Retry, RetryPolicy};
 pub use crate::authenticator_delegate::{
     AuthenticatorDelegate, DefaultAuthenticatorDelegate, PollError, PollInformation,
 };
+pub use crate::authorized_user::{AuthorizedUserAccess, AuthorizedUserKey, GOOGLE_TOKEN_URI};
+pub use crate::default_credentials::{
+    credentials_from_file, credentials_from_json, default_credentials, DefaultCredentials,
+    CREDENTIALS_ENV_VAR,
+};
 pub use crate::device::{DeviceFlow, GOOGLE_DEVICE_CODE_URL};
 pub use crate::helper::*;
-pub use crate::installed::{InstalledFlow, InstalledFlowReturnMethod};
+pub use crate::impersonated::{ImpersonatedAccess, IAM_CREDENTIALS_URL};
+pub use crate::installed::{AuthParams, InstalledFlow, InstalledFlowReturnMethod};
+pub use crate::metadata::{
+    MetadataServerAccess, DEFAULT_METADATA_HOST, METADATA_HOST_ENV_VAR, METADATA_PROBE_TIMEOUT,
+};
 pub use crate::refresh::{RefreshFlow, RefreshResult};
 pub use crate::service_account::*;
-pub use crate::storage::{DiskTokenStorage, MemoryStorage, NullStorage, TokenStorage};
+pub use crate::signed_url::{GcsSignedUrl, GCS_HOST, MAX_SIGNED_URL_EXPIRY};
+#[cfg(feature = "sqlite")]
+pub use crate::storage::SqliteTokenStorage;
+pub use crate::storage::{DiskTokenStorage, MemoryStorage, NullStorage, StoredToken, TokenStorage};
+pub use crate::token_exchange::{
+    ExchangedToken, TokenExchangeAccess, TokenExchangeFlow, TokenExchangeRequest,
+    ACCESS_TOKEN_TYPE, DEFAULT_EXCHANGED_TOKEN_LIFETIME, ID_TOKEN_TYPE, JWT_TOKEN_TYPE,
+    REFRESH_TOKEN_TYPE, TOKEN_EXCHANGE_GRANT_TYPE,
+};
 pub use crate::types::{
-    ApplicationSecret, ConsoleApplicationSecret, FlowType, Scheme, Token, TokenType,
+    ApplicationSecret, ConsoleApplicationSecret, FlowType, PartialConsentError, Scheme, Token,
+    TokenType,
 };
+pub use crate::web::{AuthorizationRequest, WebFlow};
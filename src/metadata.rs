@@ -0,0 +1,360 @@
+//! This module provides a token source (`GetToken`) backed by the metadata server available on
+//! Google Compute Engine, and on platforms built on it like Cloud Run, Cloud Functions and
+//! Kubernetes Engine. Tokens are issued for the service accounts attached to the instance, and
+//! require no credentials at all.
+//!
+//! Resources:
+//! - [Storing and retrieving instance
+//!   metadata](https://cloud.google.com/compute/docs/storing-retrieving-metadata)
+
+use std::borrow::BorrowMut;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::authenticator::GetToken;
+use crate::service_account::{LruOrder, DEFAULT_AUDIENCE_CAPACITY};
+use crate::storage::{hash_scopes, MemoryStorage, TokenStorage};
+use crate::types::{StringError, Token};
+
+use hyper::header::Headers;
+use hyper::net::{HttpStream, NetworkConnector};
+use url::form_urlencoded;
+
+/// The host name of the metadata server.
+pub const DEFAULT_METADATA_HOST: &str = "metadata.google.internal";
+/// Environment variable overriding `DEFAULT_METADATA_HOST`, for example to use a local emulator.
+/// The value may include a port, like `127.0.0.1:8080`.
+pub const METADATA_HOST_ENV_VAR: &str = "GCE_METADATA_HOST";
+/// How long `MetadataServerAccess::is_available()` waits for the metadata server by default.
+pub const METADATA_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Connects like hyper's `HttpConnector`, but gives up after a timeout.
+struct ProbeConnector(Duration);
+
+impl NetworkConnector for ProbeConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, _: &str) -> hyper::Result<HttpStream> {
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "Could not resolve host");
+        for addr in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, self.0) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(hyper::Error::Io(last_err))
+    }
+}
+
+fn metadata_request(
+    client: &mut hyper::Client,
+    host: &str,
+    path: &str,
+) -> hyper::Result<hyper::client::Response> {
+    let mut headers = Headers::new();
+    headers.set_raw("Metadata-Flavor", vec![b"Google".to_vec()]);
+    client
+        .get(&format!("http://{}/computeMetadata/v1/{}", host, path))
+        .headers(headers)
+        .send()
+}
+
+/// A token source (`GetToken`) yielding tokens for a service account attached to the current
+/// instance, as issued by the metadata server. Access and ID tokens are cached until they
+/// expire; ID tokens for at most `DEFAULT_AUDIENCE_CAPACITY` audiences (see
+/// `with_audience_capacity()`), dropping those of the least recently used audience first.
+pub struct MetadataServerAccess<C> {
+    client: C,
+    host: String,
+    service_account: String,
+    probe_timeout: Duration,
+    cache: MemoryStorage,
+    id_tokens: HashMap<String, Token>,
+    audiences: LruOrder,
+    audience_capacity: usize,
+}
+
+impl<C> MetadataServerAccess<C>
+where
+    C: BorrowMut<hyper::Client>,
+{
+    /// Returns a token source for the default service account, using the metadata server
+    /// named by `GCE_METADATA_HOST`, or `DEFAULT_METADATA_HOST` if it is not set.
+    pub fn new(client: C) -> MetadataServerAccess<C> {
+        MetadataServerAccess {
+            client,
+            host: env::var(METADATA_HOST_ENV_VAR)
+                .unwrap_or_else(|_| DEFAULT_METADATA_HOST.to_string()),
+            service_account: "default".to_string(),
+            probe_timeout: METADATA_PROBE_TIMEOUT,
+            cache: MemoryStorage::default(),
+            id_tokens: HashMap::new(),
+            audiences: LruOrder::new(),
+            audience_capacity: DEFAULT_AUDIENCE_CAPACITY,
+        }
+    }
+
+    /// Caches ID tokens for at most `capacity` audiences, instead of
+    /// `DEFAULT_AUDIENCE_CAPACITY`.
+    pub fn with_audience_capacity(mut self, capacity: usize) -> MetadataServerAccess<C> {
+        self.audience_capacity = capacity.max(1);
+        self
+    }
+
+    /// Uses the metadata server at `host`, which may include a port.
+    pub fn with_host<S: AsRef<str>>(mut self, host: S) -> MetadataServerAccess<C> {
+        self.host = host.as_ref().to_string();
+        self
+    }
+
+    /// Obtains tokens for the given service account, identified by its email address, instead
+    /// of the default service account of the instance.
+    pub fn with_service_account<S: AsRef<str>>(
+        mut self,
+        service_account: S,
+    ) -> MetadataServerAccess<C> {
+        self.service_account = service_account.as_ref().to_string();
+        self
+    }
+
+    /// Sets how long `is_available()` waits for the metadata server to accept the connection
+    /// and to respond, instead of `METADATA_PROBE_TIMEOUT`.
+    pub fn with_probe_timeout(mut self, timeout: Duration) -> MetadataServerAccess<C> {
+        self.probe_timeout = timeout;
+        self
+    }
+
+    /// Checks whether a metadata server is reachable. The check uses its own HTTP client,
+    /// which waits at most the probe timeout for connecting and for the response, so that it
+    /// fails quickly when not running on Google Cloud.
+    pub fn is_available(&mut self) -> bool {
+        let mut client = hyper::Client::with_connector(ProbeConnector(self.probe_timeout));
+        client.set_read_timeout(Some(self.probe_timeout));
+        client.set_write_timeout(Some(self.probe_timeout));
+        match metadata_request(&mut client, &self.host, "") {
+            Ok(response) => response
+                .headers
+                .get_raw("Metadata-Flavor")
+                .map(|values| values.iter().any(|v| v.as_slice() == b"Google"))
+                .unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns an ID token for `audience`, signed by Google. Such tokens are required by
+    /// services like Cloud Run or Identity-Aware Proxy. The ID token is available both as
+    /// `access_token` and `id_token` of the returned `Token`.
+    pub fn id_token(&mut self, audience: &str) -> Result<Token, Box<dyn Error>> {
+        for evicted in self.audiences.touch(audience, self.audience_capacity) {
+            self.id_tokens.remove(&evicted);
+        }
+        if let Some(token) = self.id_tokens.get(audience) {
+            if !token.expired() {
+                return Ok(token.clone());
+            }
+        }
+
+        let query = form_urlencoded::Serializer::new(String::new())
+            .append_pair("audience", audience)
+            .append_pair("format", "full")
+            .finish();
+        let path = format!(
+            "instance/service-accounts/{}/identity?{}",
+            self.service_account, query
+        );
+        let token = Token::from_id_token(self.get(&path)?)?;
+        self.id_tokens.insert(audience.to_string(), token.clone());
+
+        Ok(token)
+    }
+
+    /// Retrieves the metadata entry at `path`, relative to `/computeMetadata/v1/`.
+    fn get(&mut self, path: &str) -> Result<String, Box<dyn Error>> {
+        let mut response = metadata_request(self.client.borrow_mut(), &self.host, path)?;
+        let mut body = String::new();
+        response.read_to_string(&mut body)?;
+
+        if response.status.is_success() {
+            Ok(body)
+        } else {
+            Err(Box::new(StringError::new(
+                format!("Metadata server returned {}", response.status),
+                Some(&body),
+            )))
+        }
+    }
+
+    fn request_token(&mut self, scopes: &[&str]) -> Result<Token, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct TokenResponse {
+            access_token: String,
+            token_type: String,
+            expires_in: i64,
+        }
+
+        let mut path = format!("instance/service-accounts/{}/token", self.service_account);
+        if !scopes.is_empty() {
+            path.push('?');
+            path.push_str(
+                &form_urlencoded::Serializer::new(String::new())
+                    .append_pair("scopes", &scopes.join(","))
+                    .finish(),
+            );
+        }
+
+        let response: TokenResponse = serde_json::from_str(&self.get(&path)?)?;
+        let mut token = Token {
+            access_token: response.access_token,
+            refresh_token: String::new(),
+            token_type: response.token_type,
+            expires_in: Some(response.expires_in),
+            expires_in_timestamp: None,
+            scope: None,
+            id_token: None,
+        };
+        token.set_expiry_absolute();
+        Ok(token)
+    }
+}
+
+impl<C: BorrowMut<hyper::Client>> GetToken for MetadataServerAccess<C> {
+    /// Returns an access token for the given scopes. If no scopes are given, the token is
+    /// valid for the scopes configured for the instance.
+    fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        let (hash, scps) = hash_scopes(scopes);
+
+        if let Some(token) = self.cache.get(hash, &scps)? {
+            if !token.expired() {
+                return Ok(token);
+            }
+        }
+
+        let token = self.request_token(&scps)?;
+        let _ = self.cache.set(hash, &scps, Some(token.clone()));
+
+        Ok(token)
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::tests::MockConnector;
+
+    const METADATA_SERVER_RESPONSES: [&str; 2] = [
+        "HTTP/1.1 200 OK\r\n\
+         Metadata-Flavor: Google\r\n\
+         \r\n\
+         {\"access_token\":\"ya29.metadata\",\"expires_in\":3599,\"token_type\":\"Bearer\"}",
+        // {"aud":"https://example.com","exp":4102444800}
+        "HTTP/1.1 200 OK\r\n\
+         Metadata-Flavor: Google\r\n\
+         \r\n\
+         eyJhbGciOiJSUzI1NiIsInR5cCI6IkpXVCJ9.\
+         eyJhdWQiOiJodHRwczovL2V4YW1wbGUuY29tIiwiZXhwIjo0MTAyNDQ0ODAwfQ.c2lnbmF0dXJl",
+    ];
+
+    #[test]
+    fn metadata_tokens() {
+        let mut acc = MetadataServerAccess::new(hyper::Client::with_connector(MockConnector::new(
+            &METADATA_SERVER_RESPONSES,
+        )))
+        .with_host("127.0.0.1:8080");
+
+        let scopes = ["https://www.googleapis.com/auth/cloud-platform"];
+        let token = acc.token(&scopes).unwrap();
+        assert_eq!(token.access_token, "ya29.metadata");
+        assert!(!token.expired());
+        // Served from the cache.
+        assert_eq!(acc.token(&scopes).unwrap(), token);
+
+        let id_token = acc.id_token("https://example.com").unwrap();
+        assert!(id_token
+            .access_token
+            .starts_with("eyJhbGciOiJSUzI1NiIsInR5cCI6IkpXVCJ9."));
+        assert_eq!(id_token.id_token, Some(id_token.access_token.clone()));
+        assert_eq!(id_token.expires_in_timestamp, Some(4102444800));
+        assert_eq!(acc.id_token("https://example.com").unwrap(), id_token);
+    }
+
+    #[test]
+    fn id_token_capacity() {
+        let mut acc = MetadataServerAccess::new(hyper::Client::with_connector(MockConnector::new(
+            &[METADATA_SERVER_RESPONSES[1]; 3],
+        )))
+        .with_host("127.0.0.1:8080")
+        .with_audience_capacity(2);
+
+        for audience in &[
+            "https://a.example.com",
+            "https://b.example.com",
+            "https://c.example.com",
+        ] {
+            acc.id_token(audience).unwrap();
+        }
+        let mut cached: Vec<&String> = acc.id_tokens.keys().collect();
+        cached.sort();
+        assert_eq!(
+            cached,
+            vec!["https://b.example.com", "https://c.example.com"]
+        );
+    }
+
+    #[test]
+    fn probe() {
+        use std::io::Write;
+        use std::net::TcpListener;
+        use std::thread;
+        use std::time::Instant;
+
+        let probe = |host: String| {
+            MetadataServerAccess::new(hyper::Client::new())
+                .with_host(host)
+                .with_probe_timeout(Duration::from_millis(200))
+                .is_available()
+        };
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = server.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nMetadata-Flavor: Google\r\n\
+                      Content-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+        });
+        assert!(probe(host));
+        handle.join().unwrap();
+
+        // A server that accepts the connection, but never answers.
+        let silent = TcpListener::bind("127.0.0.1:0").unwrap();
+        let start = Instant::now();
+        assert!(!probe(silent.local_addr().unwrap().to_string()));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // Nothing listening at all.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(!probe(format!("127.0.0.1:{}", port)));
+    }
+}
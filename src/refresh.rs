@@ -46,6 +46,10 @@ where
     /// or your authorization was revoked. Therefore no further attempt shall be made,
     /// and you will have to re-authorize using the `DeviceFlow`
     ///
+    /// Some providers rotate refresh tokens, i.e. invalidate the given `refresh_token` and
+    /// return a new one. In that case, the new `Token` carries the new refresh token, and
+    /// has to be stored in place of the old one.
+    ///
     /// # Arguments
     /// * `authentication_url` - URL matching the one used in the flow that obtained
     ///                          your refresh_token in the first place.
@@ -100,6 +104,9 @@ where
             access_token: String,
             token_type: String,
             expires_in: i64,
+            refresh_token: Option<String>,
+            scope: Option<String>,
+            id_token: Option<String>,
         }
 
         match json::from_str::<JsonError>(&json_str) {
@@ -114,9 +121,11 @@ where
         self.result = RefreshResult::Success(Token {
             access_token: t.access_token,
             token_type: t.token_type,
-            refresh_token: refresh_token.to_string(),
+            refresh_token: t.refresh_token.unwrap_or_else(|| refresh_token.to_string()),
             expires_in: None,
             expires_in_timestamp: Some(Utc::now().timestamp() + t.expires_in),
+            scope: t.scope,
+            id_token: t.id_token,
         });
 
         &self.result
@@ -135,22 +144,30 @@ mod tests {
 
     struct MockGoogleRefresh(SequentialConnector);
 
-    impl Default for MockGoogleRefresh {
-        fn default() -> MockGoogleRefresh {
+    impl MockGoogleRefresh {
+        fn with_response(body: &str) -> MockGoogleRefresh {
             let mut c = MockGoogleRefresh(Default::default());
-            c.0.content.push(
+            c.0.content.push(format!(
                 "HTTP/1.1 200 OK\r\n\
                  Server: BOGUS\r\n\
                  \r\n\
-                 {\r\n\
+                 {}",
+                body
+            ));
+
+            c
+        }
+    }
+
+    impl Default for MockGoogleRefresh {
+        fn default() -> MockGoogleRefresh {
+            MockGoogleRefresh::with_response(
+                "{\r\n\
                  \"access_token\":\"1/fFAGRNJru1FTz70BzhT3Zg\",\r\n\
                  \"expires_in\":3920,\r\n\
                  \"token_type\":\"Bearer\"\r\n\
-                 }"
-                .to_string(),
-            );
-
-            c
+                 }",
+            )
         }
     }
 
@@ -178,9 +195,36 @@ mod tests {
         ) {
             RefreshResult::Success(ref t) => {
                 assert_eq!(t.access_token, "1/fFAGRNJru1FTz70BzhT3Zg");
+                assert_eq!(t.refresh_token, "bogus_refresh_token");
                 assert!(!t.expired());
             }
             _ => unreachable!(),
         }
     }
+
+    #[test]
+    fn refresh_flow_rotation() {
+        let appsecret = parse_application_secret(TEST_APP_SECRET).unwrap();
+
+        let mut c = hyper::Client::with_connector(MockGoogleRefresh::with_response(
+            r#"{"access_token":"new_access_token","expires_in":3600,"token_type":"Bearer",
+                "refresh_token":"rotated_refresh_token","scope":"openid email",
+                "id_token":"header.claims.signature"}"#,
+        ));
+        let mut flow = RefreshFlow::new(&mut c);
+
+        match *flow.refresh_token(
+            FlowType::Device(GOOGLE_DEVICE_CODE_URL.to_string()),
+            &appsecret,
+            "bogus_refresh_token",
+        ) {
+            RefreshResult::Success(ref t) => {
+                assert_eq!(t.access_token, "new_access_token");
+                assert_eq!(t.refresh_token, "rotated_refresh_token");
+                assert_eq!(t.scope, Some("openid email".to_string()));
+                assert_eq!(t.id_token, Some("header.claims.signature".to_string()));
+            }
+            _ => unreachable!(),
+        }
+    }
 }
@@ -0,0 +1,293 @@
+//! This module provides `GcsSignedUrl`, which creates V4 signed URLs for Cloud Storage objects.
+//! A signed URL grants whoever has it time-limited access to a single object, without them
+//! needing credentials of their own. URLs are signed with a service account key, or any other
+//! `JwtSigner` producing RS256 signatures.
+//!
+//! Resources:
+//! - [V4 signing process](https://cloud.google.com/storage/docs/access-control/signing-urls-manually)
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::time::Duration;
+
+use crate::service_account::{
+    missing_field, sha256, signer_from_key, Algorithm, JwtSigner, ServiceAccountKey,
+};
+use crate::types::StringError;
+
+use chrono::{DateTime, Utc};
+
+/// The host of the Cloud Storage XML API.
+pub const GCS_HOST: &str = "storage.googleapis.com";
+/// The longest validity of a signed URL allowed by Cloud Storage, seven days.
+pub const MAX_SIGNED_URL_EXPIRY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+const SIGNING_ALGORITHM: &str = "GOOG4-RSA-SHA256";
+
+/// Percent-encodes everything except the unreserved characters of RFC 3986 and the characters
+/// in `keep`.
+fn percent_encode(s: &str, keep: &[u8]) -> String {
+    let mut encoded = String::new();
+    for &b in s.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) || keep.contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A V4 signed URL for an object in Cloud Storage. By default, the URL allows to `GET` the
+/// object for one hour, starting now.
+///
+/// ```no_run
+/// # use yup_oauth2::{service_account_key_from_file, GcsSignedUrl};
+/// # use std::time::Duration;
+/// let key = service_account_key_from_file("key.json").unwrap();
+/// let url = GcsSignedUrl::new("my-bucket", "reports/2019.pdf")
+///     .with_expiry(Duration::from_secs(600))
+///     .sign(&key)
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct GcsSignedUrl {
+    method: String,
+    bucket: String,
+    object: String,
+    expiry: Duration,
+    timestamp: Option<DateTime<Utc>>,
+    host: Option<String>,
+    headers: BTreeMap<String, String>,
+    query: BTreeMap<String, String>,
+}
+
+impl GcsSignedUrl {
+    /// Returns a signed URL for `object` in `bucket`.
+    pub fn new<S: AsRef<str>, T: AsRef<str>>(bucket: S, object: T) -> GcsSignedUrl {
+        GcsSignedUrl {
+            method: "GET".to_string(),
+            bucket: bucket.as_ref().to_string(),
+            object: object.as_ref().to_string(),
+            expiry: Duration::from_secs(3600),
+            timestamp: None,
+            host: None,
+            headers: BTreeMap::new(),
+            query: BTreeMap::new(),
+        }
+    }
+
+    /// Allows the HTTP `method`, e.g. `PUT` to upload the object, instead of `GET`.
+    pub fn with_method<S: AsRef<str>>(mut self, method: S) -> GcsSignedUrl {
+        self.method = method.as_ref().to_uppercase();
+        self
+    }
+
+    /// Sets how long the URL is valid, at most `MAX_SIGNED_URL_EXPIRY`.
+    pub fn with_expiry(mut self, expiry: Duration) -> GcsSignedUrl {
+        self.expiry = expiry;
+        self
+    }
+
+    /// Makes the URL valid from `timestamp` on instead of from the time it is signed.
+    pub fn with_timestamp(mut self, timestamp: DateTime<Utc>) -> GcsSignedUrl {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    /// Uses `host` instead of `GCS_HOST`. `host` has to serve the objects of the bucket
+    /// directly, like a custom domain or `my-bucket.storage.googleapis.com`, since the bucket
+    /// isn't part of the path of the URL then.
+    pub fn with_host<S: AsRef<str>>(mut self, host: S) -> GcsSignedUrl {
+        self.host = Some(host.as_ref().to_string());
+        self
+    }
+
+    /// Requires requests using the URL to send the header `name` with `value`, e.g.
+    /// `Content-Type` for uploads.
+    pub fn with_header<S: AsRef<str>, T: AsRef<str>>(mut self, name: S, value: T) -> GcsSignedUrl {
+        let value = value
+            .as_ref()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.headers.insert(name.as_ref().to_lowercase(), value);
+        self
+    }
+
+    /// Adds the query parameter `name` with `value`, e.g. `response-content-disposition`, to
+    /// the URL.
+    pub fn with_query_param<S: AsRef<str>, T: AsRef<str>>(
+        mut self,
+        name: S,
+        value: T,
+    ) -> GcsSignedUrl {
+        self.query
+            .insert(name.as_ref().to_string(), value.as_ref().to_string());
+        self
+    }
+
+    /// Signs the URL with the private key of `key`.
+    pub fn sign(&self, key: &ServiceAccountKey) -> Result<String, Box<dyn Error>> {
+        let email = key
+            .client_email
+            .as_ref()
+            .ok_or_else(|| missing_field("client_email"))?;
+        self.sign_with(&signer_from_key(key, None)?, email)
+    }
+
+    /// Signs the URL with `signer`, which has to produce RS256 signatures with the key of the
+    /// service account `email`.
+    pub fn sign_with(&self, signer: &dyn JwtSigner, email: &str) -> Result<String, Box<dyn Error>> {
+        if signer.alg() != Algorithm::RS256 {
+            return Err(Box::new(StringError::new(
+                format!(
+                    "Signed URLs require RS256 signatures, not {}",
+                    signer.alg().as_ref()
+                ),
+                None,
+            )));
+        }
+        if self.expiry > MAX_SIGNED_URL_EXPIRY {
+            return Err(Box::new(StringError::new(
+                format!(
+                    "Signed URLs can be valid for at most {} seconds",
+                    MAX_SIGNED_URL_EXPIRY.as_secs()
+                ),
+                None,
+            )));
+        }
+
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now);
+        let scope = format!("{}/auto/storage/goog4_request", timestamp.format("%Y%m%d"));
+        let timestamp = timestamp.format("%Y%m%dT%H%M%SZ").to_string();
+
+        let object = percent_encode(&self.object, b"/");
+        let (host, path) = match self.host {
+            Some(ref host) => (host.as_str(), format!("/{}", object)),
+            None => (GCS_HOST, format!("/{}/{}", self.bucket, object)),
+        };
+
+        let mut headers = self.headers.clone();
+        headers.insert("host".to_string(), host.to_string());
+        let signed_headers = headers.keys().cloned().collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value))
+            .collect();
+
+        let mut query = self.query.clone();
+        query.insert(
+            "X-Goog-Algorithm".to_string(),
+            SIGNING_ALGORITHM.to_string(),
+        );
+        query.insert(
+            "X-Goog-Credential".to_string(),
+            format!("{}/{}", email, scope),
+        );
+        query.insert("X-Goog-Date".to_string(), timestamp.clone());
+        query.insert(
+            "X-Goog-Expires".to_string(),
+            self.expiry.as_secs().to_string(),
+        );
+        query.insert("X-Goog-SignedHeaders".to_string(), signed_headers.clone());
+        let canonical_query = query
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    percent_encode(name, b""),
+                    percent_encode(value, b"")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let canonical_request = [
+            self.method.as_str(),
+            &path,
+            &canonical_query,
+            &canonical_headers,
+            &signed_headers,
+            "UNSIGNED-PAYLOAD",
+        ]
+        .join("\n");
+        let string_to_sign = [
+            SIGNING_ALGORITHM,
+            &timestamp,
+            &scope,
+            &hex(&sha256(canonical_request.as_bytes())),
+        ]
+        .join("\n");
+        let signature = signer.sign(string_to_sign.as_bytes())?;
+
+        Ok(format!(
+            "https://{}{}?{}&X-Goog-Signature={}",
+            host,
+            path,
+            canonical_query,
+            hex(&signature)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::service_account_key_from_file;
+
+    // Signed independently with `openssl dgst -sha256 -sign`.
+    const SIGNED_URL: &str = "https://storage.googleapis.com/bucket/dir/file%20name.txt?\
+        X-Goog-Algorithm=GOOG4-RSA-SHA256&\
+        X-Goog-Credential=oauth2-public-test%40sanguine-rhythm-105020.iam.gserviceaccount.com\
+        %2F20190201%2Fauto%2Fstorage%2Fgoog4_request&X-Goog-Date=20190201T120000Z&\
+        X-Goog-Expires=600&X-Goog-SignedHeaders=content-type%3Bhost&X-Goog-Signature=\
+        2acaff2e486dd6bd89f4e1db5e81b55cc64914c583ed84219255dbb96e0606f8cc8c29b69ad1ae98daecc8228b\
+        3e4d81d67e1619c8704a79e78fe8199bedba3c3431344ef9146012e8451a5f622cb1795122933e440944d763e9\
+        701608fe5b0904bcc0b37f0da8e961cecf64e2f80df48d9624f70a6257c46f3cf9059429661e3477513544b9ee\
+        e0da6ab9edfb62a2e414e77f686205754008d6cfda3a4c966417b2cf752626ae5124e7d63c8963347d9af6c1f4\
+        1ff86e14dd78b5ee629235ada91bde687e7d666898711b735b9c4e50fbc9f6beb18c616171a7b94df348d096ae\
+        dba6bc58f94b805b98f06c33e09bddafd51f239619b5606c31ee13c61b9229";
+
+    const CUSTOM_HOST_SIGNED_URL: &str = "https://cdn.example.com/dir/file%20name.txt?\
+        X-Goog-Algorithm=GOOG4-RSA-SHA256&\
+        X-Goog-Credential=oauth2-public-test%40sanguine-rhythm-105020.iam.gserviceaccount.com\
+        %2F20190201%2Fauto%2Fstorage%2Fgoog4_request&X-Goog-Date=20190201T120000Z&\
+        X-Goog-Expires=3600&X-Goog-SignedHeaders=host&X-Goog-Signature=\
+        660c54b2936bc31853e16750641ad0933572299b7af0a23db96ef3e01e7f035e9ce690b1d686783482135a834d\
+        266b51a3099704e36061f13f915da3b20d2f520a93d60922e6ed968f8edc4c5796936082a23c0c8188bf9a0b6f\
+        71222416c695062befa555d73c40460af1976376280aac41c9968b192c3f7d2278af2ac5a545b714488ca565c7\
+        0ce3d51dadb1ddeb1f75565401eb6e96900eb3ac743623e4c2170670c210aa0b23535c05d51940563d77759a20\
+        2f3bc5b0df2e203c736469f9aa458aeeeee07076f5fca0a6f4e205e33757cf8ec2ace10f396f7c875d3e41305e\
+        8ba21fab3b33f2a6ec6505cc47517171cd7f9dece3b3081596ae05c38bcb11";
+
+    #[test]
+    fn sign_url() {
+        let key = service_account_key_from_file("examples/Sanguine-69411a0c0eea.json").unwrap();
+        let url = GcsSignedUrl::new("bucket", "dir/file name.txt")
+            .with_method("put")
+            .with_expiry(Duration::from_secs(600))
+            .with_timestamp("2019-02-01T12:00:00Z".parse().unwrap())
+            .with_header("Content-Type", " text/plain ")
+            .sign(&key)
+            .unwrap();
+        assert_eq!(url, SIGNED_URL);
+
+        // The bucket is implied by a custom host, and not part of the signed path.
+        let url = GcsSignedUrl::new("bucket", "dir/file name.txt")
+            .with_timestamp("2019-02-01T12:00:00Z".parse().unwrap())
+            .with_host("cdn.example.com")
+            .sign(&key)
+            .unwrap();
+        assert_eq!(url, CUSTOM_HOST_SIGNED_URL);
+
+        assert!(GcsSignedUrl::new("bucket", "object")
+            .with_expiry(MAX_SIGNED_URL_EXPIRY + Duration::from_secs(1))
+            .sign(&key)
+            .is_err());
+    }
+}
@@ -16,11 +16,17 @@ use std::io::{Read, Write};
 
 use crate::types::Token;
 
+use chrono::Utc;
+
 /// Implements a specialized storage to set and retrieve `Token` instances.
 /// The `scope_hash` represents the signature of the scopes for which the given token
 /// should be stored or retrieved.
 /// For completeness, the underlying, sorted scopes are provided as well. They might be
 /// useful for presentation to the user.
+///
+/// Tokens may additionally be stored per account (for example the email address of a user),
+/// which allows one storage to hold several identities for the same scopes. `set()` and `get()`
+/// operate on the default account, which is the empty string.
 pub trait TokenStorage {
     type Error: 'static + Error;
 
@@ -34,6 +40,136 @@ pub trait TokenStorage {
     ) -> Result<(), Self::Error>;
     /// A `None` result indicates that there is no token for the given scope_hash.
     fn get(&self, scope_hash: u64, scopes: &Vec<&str>) -> Result<Option<Token>, Self::Error>;
+
+    /// Like `set()`, but for the token belonging to `account`. Tokens of different accounts
+    /// have to be kept apart.
+    ///
+    /// The default implementation uses `set()`, with a scope hash which is derived from the
+    /// account for all but the default account.
+    fn set_for_account(
+        &mut self,
+        account: &str,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), Self::Error> {
+        self.set(account_scope_hash(account, scope_hash), scopes, token)
+    }
+
+    /// Like `get()`, but for the token belonging to `account`.
+    ///
+    /// The default implementation uses `get()`, like `set_for_account()`.
+    fn get_for_account(
+        &self,
+        account: &str,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+    ) -> Result<Option<Token>, Self::Error> {
+        self.get(account_scope_hash(account, scope_hash), scopes)
+    }
+
+    /// Returns all stored tokens, along with the account and scopes they are stored for.
+    ///
+    /// All other enumeration and management methods are based on it by default.
+    fn entries(&self) -> Result<Vec<StoredToken>, Self::Error>;
+
+    /// Returns all accounts for which at least one token is stored, including the default
+    /// account if applicable.
+    fn accounts(&self) -> Result<Vec<String>, Self::Error> {
+        let mut accounts: Vec<String> = self.entries()?.into_iter().map(|e| e.account).collect();
+        accounts.sort();
+        accounts.dedup();
+        Ok(accounts)
+    }
+
+    /// Removes all tokens stored for `account`.
+    fn remove_account(&mut self, account: &str) -> Result<(), Self::Error> {
+        for entry in self.entries()? {
+            if entry.account == account {
+                remove_entry(self, &entry)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes all stored tokens.
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        for entry in self.entries()? {
+            remove_entry(self, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Removes all tokens which can't be used anymore, i.e. whose access token has expired
+    /// and which lack a refresh token. Expired tokens with a refresh token are kept, as they
+    /// are refreshed on their next use.
+    fn remove_expired(&mut self) -> Result<(), Self::Error> {
+        let now = Utc::now().timestamp();
+        for entry in self.entries()? {
+            if entry.unusable_at(now) {
+                remove_entry(self, &entry)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The scope hash under which the default implementations of `TokenStorage` store the tokens of
+/// `account` in `set()`.
+fn account_scope_hash(account: &str, scope_hash: u64) -> u64 {
+    if account.is_empty() {
+        return scope_hash;
+    }
+    let mut sh = DefaultHasher::new();
+    (account, scope_hash).hash(&mut sh);
+    sh.finish()
+}
+
+fn remove_entry<S: TokenStorage + ?Sized>(
+    storage: &mut S,
+    entry: &StoredToken,
+) -> Result<(), S::Error> {
+    let scopes = entry.scopes.iter().map(|s| s.as_str()).collect();
+    storage.set_for_account(&entry.account, entry.scope_hash, &scopes, None)
+}
+
+/// A token as kept by a `TokenStorage`, together with the account and scopes it is stored for.
+///
+/// As this type can be (de)serialized, it can also be used to export tokens from one storage
+/// and import them into another one using `TokenStorage::set_for_account()`.
+#[derive(Clone, PartialEq, Debug, Deserialize, Serialize)]
+pub struct StoredToken {
+    /// The account the token belongs to; empty for the default account.
+    pub account: String,
+    /// The hash of `scopes`, as calculated by `hash_scopes()`.
+    pub scope_hash: u64,
+    /// The sorted scopes the token was stored for. This may be empty for tokens stored by
+    /// older versions of this crate.
+    pub scopes: Vec<String>,
+    pub token: Token,
+}
+
+impl StoredToken {
+    fn new(account: &str, scope_hash: u64, scopes: &[&str], token: Token) -> StoredToken {
+        StoredToken {
+            account: account.to_string(),
+            scope_hash,
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            token,
+        }
+    }
+
+    /// Whether the token has expired at `now` and can't be refreshed. Unlike
+    /// `Token::expired()`, this doesn't panic on tokens without an expiry date; these are
+    /// considered valid.
+    fn unusable_at(&self, now: i64) -> bool {
+        self.token.refresh_token.is_empty()
+            && self
+                .token
+                .expires_in_timestamp
+                .map(|ts| ts <= now)
+                .unwrap_or(false)
+    }
 }
 
 /// Calculate a hash value describing the scopes, and return a sorted Vec of the scopes.
@@ -79,12 +215,16 @@ impl TokenStorage for NullStorage {
     fn get(&self, _: u64, _: &Vec<&str>) -> Result<Option<Token>, NullError> {
         Ok(None)
     }
+    fn entries(&self) -> Result<Vec<StoredToken>, NullError> {
+        Ok(Vec::new())
+    }
 }
 
 /// A storage that remembers values for one session only.
+/// Tokens are kept per account, and then per scope hash; use `entries()` to inspect them.
 #[derive(Default)]
 pub struct MemoryStorage {
-    pub tokens: HashMap<u64, Token>,
+    tokens: HashMap<String, HashMap<u64, StoredToken>>,
 }
 
 impl TokenStorage for MemoryStorage {
@@ -93,28 +233,108 @@ impl TokenStorage for MemoryStorage {
     fn set(
         &mut self,
         scope_hash: u64,
-        _: &Vec<&str>,
+        scopes: &Vec<&str>,
         token: Option<Token>,
     ) -> Result<(), NullError> {
-        match token {
-            Some(t) => self.tokens.insert(scope_hash, t),
-            None => self.tokens.remove(&scope_hash),
-        };
+        self.set_for_account("", scope_hash, scopes, token)
+    }
+
+    fn get(&self, scope_hash: u64, scopes: &Vec<&str>) -> Result<Option<Token>, NullError> {
+        self.get_for_account("", scope_hash, scopes)
+    }
+
+    fn set_for_account(
+        &mut self,
+        account: &str,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), NullError> {
+        set_account_token(&mut self.tokens, account, scope_hash, scopes, token);
         Ok(())
     }
 
-    fn get(&self, scope_hash: u64, _: &Vec<&str>) -> Result<Option<Token>, NullError> {
-        match self.tokens.get(&scope_hash) {
-            Some(t) => Ok(Some(t.clone())),
-            None => Ok(None),
+    fn get_for_account(
+        &self,
+        account: &str,
+        scope_hash: u64,
+        _: &Vec<&str>,
+    ) -> Result<Option<Token>, NullError> {
+        Ok(self
+            .tokens
+            .get(account)
+            .and_then(|tokens| tokens.get(&scope_hash))
+            .map(|entry| entry.token.clone()))
+    }
+
+    fn entries(&self) -> Result<Vec<StoredToken>, NullError> {
+        Ok(account_entries(&self.tokens))
+    }
+
+    fn accounts(&self) -> Result<Vec<String>, NullError> {
+        Ok(self.tokens.keys().cloned().collect())
+    }
+
+    fn remove_account(&mut self, account: &str) -> Result<(), NullError> {
+        self.tokens.remove(account);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), NullError> {
+        self.tokens.clear();
+        Ok(())
+    }
+}
+
+/// Inserts or removes a token in a per-account token map, dropping accounts that end up
+/// without any tokens.
+fn set_account_token(
+    tokens: &mut HashMap<String, HashMap<u64, StoredToken>>,
+    account: &str,
+    scope_hash: u64,
+    scopes: &[&str],
+    token: Option<Token>,
+) {
+    match token {
+        Some(t) => {
+            tokens
+                .entry(account.to_string())
+                .or_default()
+                .insert(scope_hash, StoredToken::new(account, scope_hash, scopes, t));
+        }
+        None => {
+            let now_empty = match tokens.get_mut(account) {
+                Some(account_tokens) => {
+                    account_tokens.remove(&scope_hash);
+                    account_tokens.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                tokens.remove(account);
+            }
         }
     }
 }
 
+fn account_entries(tokens: &HashMap<String, HashMap<u64, StoredToken>>) -> Vec<StoredToken> {
+    tokens
+        .values()
+        .flat_map(|account_tokens| account_tokens.values().cloned())
+        .collect()
+}
+
 /// A single stored token.
 #[derive(Serialize, Deserialize)]
 struct JSONToken {
+    /// Missing in files written before accounts were supported; these tokens belong to the
+    /// default account.
+    #[serde(default)]
+    pub account: String,
     pub hash: u64,
+    /// Missing in files written before scopes were recorded.
+    #[serde(default)]
+    pub scopes: Vec<String>,
     pub token: Token,
 }
 
@@ -128,7 +348,7 @@ struct JSONTokens {
 #[derive(Default)]
 pub struct DiskTokenStorage {
     location: String,
-    tokens: HashMap<u64, Token>,
+    tokens: HashMap<String, HashMap<u64, StoredToken>>,
 }
 
 impl DiskTokenStorage {
@@ -169,7 +389,8 @@ impl DiskTokenStorage {
         }
 
         for t in tokens.tokens {
-            self.tokens.insert(t.hash, t.token);
+            let scopes: Vec<&str> = t.scopes.iter().map(|s| s.as_str()).collect();
+            set_account_token(&mut self.tokens, &t.account, t.hash, &scopes, Some(t.token));
         }
         return Result::Ok(());
     }
@@ -177,10 +398,12 @@ impl DiskTokenStorage {
     pub fn dump_to_file(&mut self) -> Result<(), io::Error> {
         let mut jsontokens = JSONTokens { tokens: Vec::new() };
 
-        for (hash, token) in self.tokens.iter() {
+        for entry in account_entries(&self.tokens) {
             jsontokens.tokens.push(JSONToken {
-                hash: *hash,
-                token: token.clone(),
+                account: entry.account,
+                hash: entry.scope_hash,
+                scopes: entry.scopes,
+                token: entry.token,
             });
         }
 
@@ -205,22 +428,435 @@ impl TokenStorage for DiskTokenStorage {
     fn set(
         &mut self,
         scope_hash: u64,
-        _: &Vec<&str>,
+        scopes: &Vec<&str>,
         token: Option<Token>,
     ) -> Result<(), Self::Error> {
+        self.set_for_account("", scope_hash, scopes, token)
+    }
+    fn get(&self, scope_hash: u64, scopes: &Vec<&str>) -> Result<Option<Token>, Self::Error> {
+        self.get_for_account("", scope_hash, scopes)
+    }
+
+    fn set_for_account(
+        &mut self,
+        account: &str,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), Self::Error> {
+        set_account_token(&mut self.tokens, account, scope_hash, scopes, token);
+        self.dump_to_file()
+    }
+
+    fn get_for_account(
+        &self,
+        account: &str,
+        scope_hash: u64,
+        _: &Vec<&str>,
+    ) -> Result<Option<Token>, Self::Error> {
+        Ok(self
+            .tokens
+            .get(account)
+            .and_then(|tokens| tokens.get(&scope_hash))
+            .map(|entry| entry.token.clone()))
+    }
+
+    fn entries(&self) -> Result<Vec<StoredToken>, Self::Error> {
+        Ok(account_entries(&self.tokens))
+    }
+
+    fn accounts(&self) -> Result<Vec<String>, Self::Error> {
+        Ok(self.tokens.keys().cloned().collect())
+    }
+
+    fn remove_account(&mut self, account: &str) -> Result<(), Self::Error> {
+        self.tokens.remove(account);
+        self.dump_to_file()
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.tokens.clear();
+        self.dump_to_file()
+    }
+
+    fn remove_expired(&mut self) -> Result<(), Self::Error> {
+        let now = Utc::now().timestamp();
+        for account_tokens in self.tokens.values_mut() {
+            account_tokens.retain(|_, entry| !entry.unusable_at(now));
+        }
+        self.tokens
+            .retain(|_, account_tokens| !account_tokens.is_empty());
+        self.dump_to_file()
+    }
+}
+
+/// Stores tokens in a SQLite database, one row per account and set of scopes.
+///
+/// Unlike `DiskTokenStorage`, setting a token only touches the affected row, which makes this
+/// storage suitable for processes managing many identities at once. Several processes may
+/// share the same database file; writers wait for each other (for up to `BUSY_TIMEOUT`)
+/// instead of failing immediately.
+///
+/// Only available with the `sqlite` feature.
+#[cfg(feature = "sqlite")]
+pub struct SqliteTokenStorage {
+    conn: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteTokenStorage {
+    /// How long to wait for a lock held by another connection before giving up.
+    pub const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+    /// Opens (or creates) the database at `path` and sets up the token table if needed.
+    pub fn new<P: AsRef<std::path::Path>>(path: P) -> Result<SqliteTokenStorage, rusqlite::Error> {
+        SqliteTokenStorage::with_connection(rusqlite::Connection::open(path)?)
+    }
+
+    /// Uses an already opened connection, e.g. one obtained by `Connection::open_in_memory()`.
+    pub fn with_connection(
+        conn: rusqlite::Connection,
+    ) -> Result<SqliteTokenStorage, rusqlite::Error> {
+        conn.busy_timeout(SqliteTokenStorage::BUSY_TIMEOUT)?;
+        // Write-ahead logging lets readers in other processes proceed while a token is written.
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+             CREATE TABLE IF NOT EXISTS tokens (
+                 account TEXT NOT NULL DEFAULT '',
+                 scopes TEXT NOT NULL,
+                 refresh_token TEXT NOT NULL,
+                 expires_at INTEGER,
+                 token TEXT NOT NULL,
+                 PRIMARY KEY (account, scopes)
+             );
+             CREATE INDEX IF NOT EXISTS tokens_expires_at ON tokens (expires_at);",
+        )?;
+        Ok(SqliteTokenStorage { conn })
+    }
+}
+
+// Rows are keyed by the sorted, space-separated scopes rather than by the scope hash, as the
+// hash is not guaranteed to be stable between builds of different programs sharing the database.
+#[cfg(feature = "sqlite")]
+impl TokenStorage for SqliteTokenStorage {
+    type Error = rusqlite::Error;
+
+    fn set(
+        &mut self,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), rusqlite::Error> {
+        self.set_for_account("", scope_hash, scopes, token)
+    }
+
+    fn get(&self, scope_hash: u64, scopes: &Vec<&str>) -> Result<Option<Token>, rusqlite::Error> {
+        self.get_for_account("", scope_hash, scopes)
+    }
+
+    fn set_for_account(
+        &mut self,
+        account: &str,
+        _: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), rusqlite::Error> {
+        let scopes = scopes.join(" ");
         match token {
-            None => {
-                self.tokens.remove(&scope_hash);
-                ()
-            }
+            None => self.conn.execute(
+                "DELETE FROM tokens WHERE account = ?1 AND scopes = ?2",
+                &[account, &scopes],
+            ),
             Some(t) => {
-                self.tokens.insert(scope_hash, t.clone());
-                ()
+                let serialized = serde_json::to_string(&t)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+                self.conn.execute(
+                    "INSERT OR REPLACE INTO tokens (account, scopes, refresh_token, expires_at, token)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                    &[
+                        &account as &dyn rusqlite::types::ToSql,
+                        &scopes,
+                        &t.refresh_token,
+                        &t.expires_in_timestamp,
+                        &serialized,
+                    ],
+                )
             }
         }
-        self.dump_to_file()
+        .map(|_| ())
+    }
+
+    fn get_for_account(
+        &self,
+        account: &str,
+        _: u64,
+        scopes: &Vec<&str>,
+    ) -> Result<Option<Token>, rusqlite::Error> {
+        use rusqlite::OptionalExtension;
+
+        let serialized: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT token FROM tokens WHERE account = ?1 AND scopes = ?2",
+                &[account, &scopes.join(" ")],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match serialized {
+            None => Ok(None),
+            Some(s) => serde_json::from_str(&s).map(Some).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    0,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            }),
+        }
+    }
+
+    fn entries(&self) -> Result<Vec<StoredToken>, rusqlite::Error> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT account, scopes, token FROM tokens")?;
+        let rows = stmt.query_map(rusqlite::NO_PARAMS, |row| {
+            let account: String = row.get(0)?;
+            let scopes: String = row.get(1)?;
+            let serialized: String = row.get(2)?;
+            let token = serde_json::from_str(&serialized).map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    2,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?;
+            let scopes: Vec<&str> = scopes.split(' ').filter(|s| !s.is_empty()).collect();
+            let (scope_hash, scopes) = hash_scopes(&scopes);
+            Ok(StoredToken::new(&account, scope_hash, &scopes, token))
+        })?;
+        rows.collect()
+    }
+
+    fn accounts(&self) -> Result<Vec<String>, rusqlite::Error> {
+        let mut stmt = self.conn.prepare("SELECT DISTINCT account FROM tokens")?;
+        let accounts = stmt.query_map(rusqlite::NO_PARAMS, |row| row.get(0))?;
+        accounts.collect()
+    }
+
+    fn remove_account(&mut self, account: &str) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute("DELETE FROM tokens WHERE account = ?1", &[account])
+            .map(|_| ())
+    }
+
+    fn clear(&mut self) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute("DELETE FROM tokens", rusqlite::NO_PARAMS)
+            .map(|_| ())
+    }
+
+    fn remove_expired(&mut self) -> Result<(), rusqlite::Error> {
+        self.conn
+            .execute(
+                "DELETE FROM tokens WHERE expires_at <= ?1 AND refresh_token = ''",
+                [Utc::now().timestamp()],
+            )
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_token(access_token: &str) -> Token {
+        Token {
+            access_token: access_token.to_string(),
+            refresh_token: "refresh".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_in: None,
+            expires_in_timestamp: Some(1_000_000_000),
+            scope: None,
+            id_token: None,
+        }
+    }
+
+    #[test]
+    fn memory_storage_accounts() {
+        let mut storage = MemoryStorage::default();
+        let (hash, scopes) = hash_scopes(&["scope1"]);
+
+        storage
+            .set(hash, &scopes, Some(test_token("default")))
+            .unwrap();
+        storage
+            .set_for_account("work@example.com", hash, &scopes, Some(test_token("work")))
+            .unwrap();
+
+        let mut accounts = storage.accounts().unwrap();
+        accounts.sort();
+        assert_eq!(
+            accounts,
+            vec!["".to_string(), "work@example.com".to_string()]
+        );
+        assert_eq!(
+            storage
+                .get_for_account("work@example.com", hash, &scopes)
+                .unwrap()
+                .unwrap()
+                .access_token,
+            "work"
+        );
+        assert_eq!(
+            storage.get(hash, &scopes).unwrap().unwrap().access_token,
+            "default"
+        );
+
+        storage.remove_account("work@example.com").unwrap();
+        assert_eq!(storage.accounts().unwrap(), vec!["".to_string()]);
+        assert_eq!(
+            storage
+                .get_for_account("work@example.com", hash, &scopes)
+                .unwrap(),
+            None
+        );
+    }
+
+    /// A storage which keeps tokens by scope hash only, relying on the default per-account
+    /// methods.
+    #[derive(Default)]
+    struct ScopeStorage(HashMap<u64, Token>);
+
+    impl TokenStorage for ScopeStorage {
+        type Error = NullError;
+
+        fn set(&mut self, hash: u64, _: &Vec<&str>, token: Option<Token>) -> Result<(), NullError> {
+            match token {
+                Some(token) => self.0.insert(hash, token),
+                None => self.0.remove(&hash),
+            };
+            Ok(())
+        }
+
+        fn get(&self, hash: u64, _: &Vec<&str>) -> Result<Option<Token>, NullError> {
+            Ok(self.0.get(&hash).cloned())
+        }
+
+        fn entries(&self) -> Result<Vec<StoredToken>, NullError> {
+            Ok(self
+                .0
+                .iter()
+                .map(|(hash, token)| StoredToken::new("", *hash, &[], token.clone()))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn default_account_methods() {
+        let mut storage = ScopeStorage::default();
+        let (hash, scopes) = hash_scopes(&["scope1"]);
+
+        storage
+            .set(hash, &scopes, Some(test_token("default")))
+            .unwrap();
+        storage
+            .set_for_account("work@example.com", hash, &scopes, Some(test_token("work")))
+            .unwrap();
+        let token = |account| {
+            storage
+                .get_for_account(account, hash, &scopes)
+                .unwrap()
+                .map(|t| t.access_token)
+        };
+        assert_eq!(token(""), Some("default".to_string()));
+        assert_eq!(token("work@example.com"), Some("work".to_string()));
+        assert_eq!(token("home@example.com"), None);
     }
-    fn get(&self, scope_hash: u64, _: &Vec<&str>) -> Result<Option<Token>, Self::Error> {
-        Result::Ok(self.tokens.get(&scope_hash).map(|tok| tok.clone()))
+
+    #[test]
+    fn memory_storage_management() {
+        let mut storage = MemoryStorage::default();
+        let (hash, scopes) = hash_scopes(&["scope2", "scope1"]);
+        let (other_hash, other_scopes) = hash_scopes(&["scope3"]);
+        let mut valid = test_token("valid");
+        valid.expires_in_timestamp = Some(Utc::now().timestamp() + 3600);
+        let mut unusable = test_token("unusable");
+        unusable.refresh_token = String::new();
+
+        storage
+            .set(hash, &scopes, Some(test_token("expired")))
+            .unwrap();
+        storage
+            .set_for_account("work@example.com", other_hash, &other_scopes, Some(valid))
+            .unwrap();
+        storage
+            .set_for_account("old@example.com", hash, &scopes, Some(unusable))
+            .unwrap();
+
+        let mut entries = storage.entries().unwrap();
+        entries.sort_by(|a, b| a.account.cmp(&b.account));
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].account, "");
+        assert_eq!(entries[0].scope_hash, hash);
+        assert_eq!(entries[0].scopes, vec!["scope1", "scope2"]);
+        assert_eq!(entries[2].account, "work@example.com");
+
+        // Only the expired token without refresh token is removed.
+        storage.remove_expired().unwrap();
+        let mut entries = storage.entries().unwrap();
+        entries.sort_by(|a, b| a.account.cmp(&b.account));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].token.access_token, "expired");
+        assert_eq!(entries[1].token.access_token, "valid");
+
+        storage.clear().unwrap();
+        assert!(storage.entries().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_storage() {
+        let mut storage =
+            SqliteTokenStorage::with_connection(rusqlite::Connection::open_in_memory().unwrap())
+                .unwrap();
+        let (hash, scopes) = hash_scopes(&["scope2", "scope1"]);
+        let token = test_token("access");
+
+        assert_eq!(storage.get(hash, &scopes).unwrap(), None);
+        storage.set(hash, &scopes, Some(token.clone())).unwrap();
+        assert_eq!(storage.get(hash, &scopes).unwrap(), Some(token));
+
+        let (other_hash, other_scopes) = hash_scopes(&["scope1"]);
+        assert_eq!(storage.get(other_hash, &other_scopes).unwrap(), None);
+
+        storage
+            .set_for_account("work@example.com", hash, &scopes, Some(test_token("work")))
+            .unwrap();
+        assert_eq!(
+            storage.get(hash, &scopes).unwrap().unwrap().access_token,
+            "access"
+        );
+        let mut accounts = storage.accounts().unwrap();
+        accounts.sort();
+        assert_eq!(
+            accounts,
+            vec!["".to_string(), "work@example.com".to_string()]
+        );
+
+        let mut unusable = test_token("unusable");
+        unusable.refresh_token = String::new();
+        storage
+            .set_for_account("old@example.com", hash, &scopes, Some(unusable))
+            .unwrap();
+        storage.remove_expired().unwrap();
+        assert!(storage
+            .get_for_account("old@example.com", hash, &scopes)
+            .unwrap()
+            .is_none());
+        assert!(storage.get(hash, &scopes).unwrap().is_some());
+
+        storage.set(hash, &scopes, None).unwrap();
+        assert_eq!(storage.get(hash, &scopes).unwrap(), None);
+        storage.remove_account("work@example.com").unwrap();
+        assert!(storage.accounts().unwrap().is_empty());
     }
 }
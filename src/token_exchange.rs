@@ -0,0 +1,418 @@
+//! This module implements the [OAuth 2.0 Token Exchange](https://tools.ietf.org/html/rfc8693)
+//! (RFC 8693), which trades a token for another one, e.g. for a different audience or with
+//! fewer scopes. It is used by Google's Security Token Service, but also by other servers
+//! like Keycloak.
+//!
+//! `TokenExchangeFlow` performs single exchanges, while `TokenExchangeAccess` is a token
+//! source (`GetToken`) exchanging the tokens of another token source.
+
+use std::borrow::BorrowMut;
+use std::error::Error;
+use std::io::Read;
+use std::time::Duration;
+
+use crate::authenticator::GetToken;
+use crate::storage::{hash_scopes, MemoryStorage, TokenStorage};
+use crate::types::{JsonError, StringError, Token};
+
+use chrono::Utc;
+use hyper::header::ContentType;
+use url::form_urlencoded;
+
+/// The grant type of token exchange requests.
+pub const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+/// Token type identifier of OAuth 2.0 access tokens.
+pub const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+/// Token type identifier of OAuth 2.0 refresh tokens.
+pub const REFRESH_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:refresh_token";
+/// Token type identifier of OpenID Connect ID tokens.
+pub const ID_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:id_token";
+/// Token type identifier of JSON Web Tokens.
+pub const JWT_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:jwt";
+/// The lifetime assumed for exchanged tokens if the server doesn't report it.
+pub const DEFAULT_EXCHANGED_TOKEN_LIFETIME: Duration = Duration::from_secs(3600);
+
+/// The parameters of a token exchange request. Only `subject_token` and `subject_token_type`
+/// are required; all other parameters are omitted from the request if unset.
+#[derive(Clone, Debug, Default)]
+pub struct TokenExchangeRequest {
+    /// The token to exchange, representing the party on whose behalf the request is made.
+    pub subject_token: String,
+    /// The type of `subject_token`, e.g. `ACCESS_TOKEN_TYPE`. If empty, the subject token type
+    /// of the `TokenExchangeFlow` is used.
+    pub subject_token_type: String,
+    /// A token representing the acting party, for delegation.
+    pub actor_token: Option<String>,
+    /// The type of `actor_token`; required if `actor_token` is set.
+    pub actor_token_type: Option<String>,
+    /// The type of the requested token. The server chooses if unset.
+    pub requested_token_type: Option<String>,
+    /// The logical name of the service the token is intended for.
+    pub audience: Option<String>,
+    /// The URI of the resource the token is intended for.
+    pub resource: Option<String>,
+    /// The scopes of the requested token.
+    pub scope: Vec<String>,
+}
+
+/// The result of a successful token exchange.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExchangedToken {
+    /// The issued token. If the server didn't report its lifetime, `expires_in` is `None`, and
+    /// the token is assumed to expire after `DEFAULT_EXCHANGED_TOKEN_LIFETIME`.
+    pub token: Token,
+    /// The type of the issued token, e.g. `ACCESS_TOKEN_TYPE`.
+    pub issued_token_type: String,
+}
+
+/// Performs token exchanges against the token endpoint at `token_url`.
+pub struct TokenExchangeFlow<C> {
+    client: C,
+    token_url: String,
+    client_credentials: Option<(String, String)>,
+    subject_token_type: String,
+}
+
+impl<C> TokenExchangeFlow<C>
+where
+    C: BorrowMut<hyper::Client>,
+{
+    pub fn new<S: AsRef<str>>(client: C, token_url: S) -> TokenExchangeFlow<C> {
+        TokenExchangeFlow {
+            client,
+            token_url: token_url.as_ref().to_string(),
+            client_credentials: None,
+            subject_token_type: ACCESS_TOKEN_TYPE.to_string(),
+        }
+    }
+
+    /// Uses `token_type` as type of the subject tokens of requests which don't name one,
+    /// instead of `ACCESS_TOKEN_TYPE`.
+    pub fn with_subject_token_type<S: AsRef<str>>(mut self, token_type: S) -> TokenExchangeFlow<C> {
+        self.subject_token_type = token_type.as_ref().to_string();
+        self
+    }
+
+    /// Authenticates the exchange requests as the given client, as required by most servers
+    /// other than Google's Security Token Service.
+    pub fn with_client_credentials<S: AsRef<str>>(
+        mut self,
+        client_id: S,
+        client_secret: S,
+    ) -> TokenExchangeFlow<C> {
+        self.client_credentials = Some((
+            client_id.as_ref().to_string(),
+            client_secret.as_ref().to_string(),
+        ));
+        self
+    }
+
+    /// Encodes the form parameters of `request`, whose scopes are joined into `scope`.
+    fn request_body(&self, request: &TokenExchangeRequest, scope: &str) -> String {
+        let subject_token_type = if request.subject_token_type.is_empty() {
+            &self.subject_token_type
+        } else {
+            &request.subject_token_type
+        };
+        let mut params = form_urlencoded::Serializer::new(String::new());
+        params
+            .append_pair("grant_type", TOKEN_EXCHANGE_GRANT_TYPE)
+            .append_pair("subject_token", &request.subject_token)
+            .append_pair("subject_token_type", subject_token_type);
+        let optional = [
+            ("actor_token", request.actor_token.as_deref()),
+            ("actor_token_type", request.actor_token_type.as_deref()),
+            (
+                "requested_token_type",
+                request.requested_token_type.as_deref(),
+            ),
+            ("audience", request.audience.as_deref()),
+            ("resource", request.resource.as_deref()),
+            ("scope", Some(scope).filter(|s| !s.is_empty())),
+        ];
+        for (name, value) in optional.iter() {
+            if let Some(value) = value {
+                params.append_pair(name, value);
+            }
+        }
+        if let Some((ref id, ref secret)) = self.client_credentials {
+            params
+                .append_pair("client_id", id)
+                .append_pair("client_secret", secret);
+        }
+        params.finish()
+    }
+
+    /// Exchanges a token as described by `request`.
+    pub fn exchange(
+        &mut self,
+        request: &TokenExchangeRequest,
+    ) -> Result<ExchangedToken, Box<dyn Error>> {
+        let scope = request.scope.join(" ");
+        let body = self.request_body(request, &scope);
+
+        let mut result = self
+            .client
+            .borrow_mut()
+            .post(&self.token_url)
+            .body(&body)
+            .header(ContentType(
+                "application/x-www-form-urlencoded".parse().unwrap(),
+            ))
+            .send()?;
+        let mut response = String::new();
+        result.read_to_string(&mut response)?;
+
+        if let Ok(err) = serde_json::from_str::<JsonError>(&response) {
+            return Err(Box::new(StringError::new(
+                err.error,
+                err.error_description.as_ref(),
+            )));
+        }
+        if !result.status.is_success() {
+            return Err(Box::new(StringError::new(
+                format!("Token exchange failed with {}", result.status),
+                Some(&response),
+            )));
+        }
+
+        #[derive(Deserialize)]
+        struct ExchangeResponse {
+            access_token: String,
+            issued_token_type: String,
+            token_type: String,
+            expires_in: Option<i64>,
+            scope: Option<String>,
+            refresh_token: Option<String>,
+        }
+
+        let exchanged: ExchangeResponse = serde_json::from_str(&response)?;
+        let mut token = Token {
+            access_token: exchanged.access_token,
+            refresh_token: exchanged.refresh_token.unwrap_or_default(),
+            token_type: exchanged.token_type,
+            expires_in: exchanged.expires_in,
+            expires_in_timestamp: None,
+            scope: exchanged
+                .scope
+                .or_else(|| Some(scope).filter(|s| !s.is_empty())),
+            id_token: None,
+        };
+        if token.expires_in.is_some() {
+            token.set_expiry_absolute();
+        } else {
+            token.expires_in_timestamp =
+                Some(Utc::now().timestamp() + DEFAULT_EXCHANGED_TOKEN_LIFETIME.as_secs() as i64);
+        }
+        Ok(ExchangedToken {
+            token,
+            issued_token_type: exchanged.issued_token_type,
+        })
+    }
+}
+
+/// A token source (`GetToken`) exchanging the access tokens of another token source, the
+/// subject, for tokens with the requested scopes. Exchanged tokens are cached per set of
+/// scopes; if the server doesn't report their lifetime, they expire with the subject token, or
+/// after `DEFAULT_EXCHANGED_TOKEN_LIFETIME` if the subject token has no expiry either.
+pub struct TokenExchangeAccess<G, C> {
+    subject: G,
+    subject_scopes: Vec<String>,
+    flow: TokenExchangeFlow<C>,
+    template: TokenExchangeRequest,
+    cache: MemoryStorage,
+}
+
+impl<G, C> TokenExchangeAccess<G, C>
+where
+    G: GetToken,
+    C: BorrowMut<hyper::Client>,
+{
+    /// Returns a token source exchanging access tokens of `subject` using `flow`.
+    pub fn new(subject: G, flow: TokenExchangeFlow<C>) -> TokenExchangeAccess<G, C> {
+        TokenExchangeAccess {
+            subject,
+            subject_scopes: Vec::new(),
+            flow,
+            template: TokenExchangeRequest::default(),
+            cache: MemoryStorage::default(),
+        }
+    }
+
+    /// Requests subject tokens for the given scopes.
+    pub fn with_subject_scopes<I, T>(mut self, scopes: I) -> TokenExchangeAccess<G, C>
+    where
+        T: AsRef<str>,
+        I: IntoIterator<Item = T>,
+    {
+        self.subject_scopes = scopes.into_iter().map(|s| s.as_ref().to_string()).collect();
+        self
+    }
+
+    /// Requests tokens for the given audience.
+    pub fn with_audience<S: AsRef<str>>(mut self, audience: S) -> TokenExchangeAccess<G, C> {
+        self.template.audience = Some(audience.as_ref().to_string());
+        self
+    }
+
+    /// Requests tokens for the given resource.
+    pub fn with_resource<S: AsRef<str>>(mut self, resource: S) -> TokenExchangeAccess<G, C> {
+        self.template.resource = Some(resource.as_ref().to_string());
+        self
+    }
+
+    /// Requests tokens of the given type instead of letting the server choose.
+    pub fn with_requested_token_type<S: AsRef<str>>(
+        mut self,
+        token_type: S,
+    ) -> TokenExchangeAccess<G, C> {
+        self.template.requested_token_type = Some(token_type.as_ref().to_string());
+        self
+    }
+
+    /// Presents the given actor token along with every subject token.
+    pub fn with_actor_token<S: AsRef<str>>(
+        mut self,
+        actor_token: S,
+        actor_token_type: S,
+    ) -> TokenExchangeAccess<G, C> {
+        self.template.actor_token = Some(actor_token.as_ref().to_string());
+        self.template.actor_token_type = Some(actor_token_type.as_ref().to_string());
+        self
+    }
+}
+
+impl<G, C> GetToken for TokenExchangeAccess<G, C>
+where
+    G: GetToken,
+    C: BorrowMut<hyper::Client>,
+{
+    fn token<'b, I, T>(&mut self, scopes: I) -> Result<Token, Box<dyn Error>>
+    where
+        T: AsRef<str> + Ord + 'b,
+        I: IntoIterator<Item = &'b T>,
+    {
+        let (hash, scps) = hash_scopes(scopes);
+
+        if let Some(token) = self.cache.get(hash, &scps)? {
+            if !token.expired() {
+                return Ok(token);
+            }
+        }
+
+        let subject = self.subject.token(&self.subject_scopes)?;
+        let request = TokenExchangeRequest {
+            subject_token: subject.access_token.clone(),
+            scope: scps.iter().map(|s| s.to_string()).collect(),
+            ..self.template.clone()
+        };
+        let mut token = self.flow.exchange(&request)?.token;
+        if token.expires_in.is_none() && subject.expires_in_timestamp.is_some() {
+            token.expires_in_timestamp = subject.expires_in_timestamp;
+        }
+        let _ = self.cache.set(hash, &scps, Some(token.clone()));
+
+        Ok(token)
+    }
+
+    fn api_key(&mut self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::authorized_user::{AuthorizedUserAccess, AuthorizedUserKey};
+    use crate::types::tests::MockConnector;
+
+    const SUBJECT_RESPONSE: &str = "HTTP/1.1 200 OK\r\n\
+         \r\n\
+         {\"access_token\":\"subject\",\"expires_in\":3600,\"token_type\":\"Bearer\"}";
+
+    const EXCHANGE_RESPONSES: [&str; 2] = [
+        // Exchanged token, without a lifetime.
+        "HTTP/1.1 200 OK\r\n\
+         \r\n\
+         {\"access_token\":\"exchanged\",\"token_type\":\"Bearer\",\
+         \"issued_token_type\":\"urn:ietf:params:oauth:token-type:access_token\"}",
+        "HTTP/1.1 400 Bad Request\r\n\
+         \r\n\
+         {\"error\":\"invalid_target\",\"error_description\":\"unknown audience\"}",
+    ];
+
+    #[test]
+    fn exchange_subject_tokens() {
+        let subject = AuthorizedUserAccess::new(
+            AuthorizedUserKey {
+                key_type: None,
+                client_id: "id".to_string(),
+                client_secret: "secret".to_string(),
+                refresh_token: "refresh".to_string(),
+                quota_project_id: None,
+            },
+            hyper::Client::with_connector(MockConnector::new(&[SUBJECT_RESPONSE])),
+        );
+        let flow = TokenExchangeFlow::new(
+            hyper::Client::with_connector(MockConnector::new(&EXCHANGE_RESPONSES)),
+            "https://keycloak.example.com/realms/mesh/protocol/openid-connect/token",
+        )
+        .with_client_credentials("gateway", "secret");
+        let mut acc = TokenExchangeAccess::new(subject, flow).with_audience("billing");
+
+        let token = acc.token(&["read"]).unwrap();
+        assert_eq!(token.access_token, "exchanged");
+        assert_eq!(token.scope, Some("read".to_string()));
+        // The exchanged token expires with the subject token, which comes from its cache.
+        let subject = acc.subject.token(&acc.subject_scopes).unwrap();
+        assert!(subject.expires_in_timestamp.is_some());
+        assert_eq!(token.expires_in_timestamp, subject.expires_in_timestamp);
+        assert_eq!(acc.token(&["read"]).unwrap(), token);
+
+        let err = acc.token(&["write"]).unwrap_err();
+        assert!(err.to_string().contains("invalid_target"));
+    }
+
+    #[test]
+    fn exchange_without_lifetime() {
+        let mut flow = TokenExchangeFlow::new(
+            hyper::Client::with_connector(MockConnector::new(&EXCHANGE_RESPONSES)),
+            "https://sts.googleapis.com/v1/token",
+        );
+        let lifetime = DEFAULT_EXCHANGED_TOKEN_LIFETIME.as_secs() as i64;
+        let before = Utc::now().timestamp();
+        let exchanged = flow
+            .exchange(&TokenExchangeRequest {
+                subject_token: "subject".to_string(),
+                subject_token_type: ACCESS_TOKEN_TYPE.to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        let after = Utc::now().timestamp();
+        assert_eq!(exchanged.token.expires_in, None);
+        let expiry = exchanged.token.expires_in_timestamp.unwrap();
+        assert!(before + lifetime <= expiry && expiry <= after + lifetime);
+    }
+
+    #[test]
+    fn subject_token_type() {
+        let flow =
+            TokenExchangeFlow::new(hyper::Client::new(), "https://sts.googleapis.com/v1/token")
+                .with_subject_token_type(ID_TOKEN_TYPE);
+        let mut request = TokenExchangeRequest {
+            subject_token: "subject".to_string(),
+            ..Default::default()
+        };
+        let body = flow.request_body(&request, "");
+        assert!(
+            body.contains("subject_token_type=urn%3Aietf%3Aparams%3Aoauth%3Atoken-type%3Aid_token")
+        );
+
+        // A type named by the request takes precedence.
+        request.subject_token_type = ACCESS_TOKEN_TYPE.to_string();
+        let body = flow.request_body(&request, "");
+        assert!(body.contains(
+            "subject_token_type=urn%3Aietf%3Aparams%3Aoauth%3Atoken-type%3Aaccess_token"
+        ));
+    }
+}
@@ -102,6 +102,24 @@ impl Error for StringError {
     }
 }
 
+/// Returned by the `Authenticator` if the user granted only some of the requested scopes,
+/// which is possible with Google's granular consent screen.
+#[derive(Debug)]
+pub struct PartialConsentError {
+    /// The requested scopes that were not granted.
+    pub missing_scopes: Vec<String>,
+    /// The token obtained for the granted scopes; it is stored for these scopes only.
+    pub token: Token,
+}
+
+impl fmt::Display for PartialConsentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "Scopes not granted: {}", self.missing_scopes.join(" "))
+    }
+}
+
+impl Error for PartialConsentError {}
+
 /// Represents all implemented token types
 #[derive(Clone, PartialEq, Debug)]
 pub enum TokenType {
@@ -189,6 +207,10 @@ pub struct Token {
     /// timestamp is seconds since epoch indicating when the token will expire in absolute terms.
     /// use expiry_date() to convert to DateTime.
     pub expires_in_timestamp: Option<i64>,
+    /// The space-separated scopes granted by the server, if it told us.
+    pub scope: Option<String>,
+    /// An OpenID Connect ID token, if the server issued one along with the access token.
+    pub id_token: Option<String>,
 }
 
 impl Token {
@@ -203,6 +225,14 @@ impl Token {
         self.expiry_date() <= Utc::now()
     }
 
+    /// Returns the scopes granted by the server, or `None` if the server didn't say which
+    /// scopes it granted.
+    pub fn granted_scopes(&self) -> Option<Vec<&str>> {
+        self.scope
+            .as_ref()
+            .map(|scope| scope.split_whitespace().collect())
+    }
+
     /// Returns a DateTime object representing our expiry date.
     pub fn expiry_date(&self) -> DateTime<Utc> {
         Utc.timestamp(
@@ -212,6 +242,31 @@ impl Token {
         )
     }
 
+    /// Wraps an OpenID Connect ID token, which is used as bearer token by some services.
+    /// The expiry is taken from the token's `exp` claim; the signature is not verified.
+    pub(crate) fn from_id_token(id_token: String) -> Result<Token, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct IdTokenClaims {
+            exp: i64,
+        }
+
+        let claims = id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| StringError::from("Malformed ID token".to_string()))?;
+        let claims: IdTokenClaims =
+            serde_json::from_slice(&base64::decode_config(claims, base64::URL_SAFE_NO_PAD)?)?;
+        Ok(Token {
+            access_token: id_token.clone(),
+            refresh_token: String::new(),
+            token_type: "Bearer".to_string(),
+            expires_in: None,
+            expires_in_timestamp: Some(claims.exp),
+            scope: None,
+            id_token: Some(id_token),
+        })
+    }
+
     /// Adjust our stored expiry format to be absolute, using the current time.
     pub fn set_expiry_absolute(&mut self) -> &mut Token {
         if self.expires_in_timestamp.is_some() {
@@ -280,6 +335,27 @@ pub struct ConsoleApplicationSecret {
 pub mod tests {
     use super::*;
     use hyper;
+    use yup_hyper_mock::{MockStream, SequentialConnector};
+
+    /// A connector answering the requests of a test with canned HTTP `responses`, in order.
+    pub struct MockConnector(SequentialConnector);
+
+    impl MockConnector {
+        pub fn new<S: AsRef<str>>(responses: &[S]) -> MockConnector {
+            let mut c = MockConnector(Default::default());
+            c.0.content
+                .extend(responses.iter().map(|r| r.as_ref().to_string()));
+            c
+        }
+    }
+
+    impl hyper::net::NetworkConnector for MockConnector {
+        type Stream = MockStream;
+
+        fn connect(&self, host: &str, port: u16, scheme: &str) -> ::hyper::Result<MockStream> {
+            self.0.connect(host, port, scheme)
+        }
+    }
 
     pub const SECRET: &'static str =
         "{\"installed\":{\"auth_uri\":\"https://accounts.google.com/o/oauth2/auth\",\
@@ -0,0 +1,297 @@
+//! This module provides `WebFlow`, the authorization code flow for confidential web
+//! applications, which receive the authorization response with their own HTTP server.
+//!
+//! The flow keeps no state between the two steps: `WebFlow::authorization_request()` returns
+//! the URL to redirect the user to, along with the state, PKCE verifier and nonce, which the
+//! application keeps in the user's session until the authorization server redirects back to
+//! it. `WebFlow::exchange_code()` then checks the returned state and trades the code for a
+//! token.
+//!
+//! Resources:
+//! - [Using OAuth 2.0 for Web Server Applications](https://developers.google.com/identity/protocols/OAuth2WebServer)
+//! - [PKCE](https://tools.ietf.org/html/rfc7636)
+
+use std::borrow::BorrowMut;
+use std::error::Error;
+
+use crate::installed::{build_authentication_request_url, request_token, AuthParams};
+use crate::service_account::{constant_time_eq, random_bytes, sha256};
+use crate::storage::{hash_scopes, TokenStorage};
+use crate::types::{ApplicationSecret, StringError, Token};
+
+fn random_string() -> Result<String, Box<dyn Error>> {
+    Ok(base64::encode_config(
+        &random_bytes(32)?,
+        base64::URL_SAFE_NO_PAD,
+    ))
+}
+
+/// A pending authorization request, as returned by `WebFlow::authorization_request()`. It can
+/// be serialized to keep it in the user's session until the authorization response arrives.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    /// The URL to redirect the user to.
+    pub url: String,
+    /// The requested scopes.
+    pub scopes: Vec<String>,
+    /// The `state` parameter, which the authorization response has to return unchanged.
+    pub state: String,
+    /// The PKCE code verifier, unless PKCE is disabled.
+    pub code_verifier: Option<String>,
+    /// The `nonce` parameter, which the server includes in the ID token.
+    pub nonce: String,
+}
+
+/// The authorization code flow for web applications, using the `web` secret of a
+/// `ConsoleApplicationSecret`.
+///
+/// ```no_run
+/// # use yup_oauth2::{ApplicationSecret, AuthParams, MemoryStorage, WebFlow};
+/// # fn session() -> (yup_oauth2::AuthorizationRequest, String, String) { unimplemented!() }
+/// # let secret = ApplicationSecret::default();
+/// # let client = hyper::Client::new();
+/// let mut flow = WebFlow::new(client, &secret, "https://example.com/oauth2callback")
+///     .with_auth_params(AuthParams::default().offline());
+/// let request = flow
+///     .authorization_request(&["https://www.googleapis.com/auth/drive"])
+///     .unwrap();
+/// // Store `request` in the session and redirect the user to `request.url`. Once the user
+/// // returns with `state` and `code`:
+/// let (request, state, code) = session();
+/// let mut storage = MemoryStorage::default();
+/// let token = flow
+///     .exchange_code_into(&request, &state, &code, &mut storage, "user@example.com")
+///     .unwrap();
+/// ```
+pub struct WebFlow<C> {
+    client: C,
+    secret: ApplicationSecret,
+    redirect_uri: String,
+    auth_params: AuthParams,
+    pkce: bool,
+}
+
+impl<C> WebFlow<C>
+where
+    C: BorrowMut<hyper::Client>,
+{
+    /// Returns a flow for `secret`, whose authorization responses are sent to
+    /// `redirect_uri`, one of the redirect URIs registered for the client.
+    pub fn new<S: AsRef<str>>(
+        client: C,
+        secret: &ApplicationSecret,
+        redirect_uri: S,
+    ) -> WebFlow<C> {
+        WebFlow {
+            client,
+            secret: secret.clone(),
+            redirect_uri: redirect_uri.as_ref().to_string(),
+            auth_params: AuthParams::default(),
+            pkce: true,
+        }
+    }
+
+    /// Adds `params` to the authorization requests. A nonce set in `params` is used instead
+    /// of a random one.
+    pub fn with_auth_params(mut self, params: AuthParams) -> WebFlow<C> {
+        self.auth_params = params;
+        self
+    }
+
+    /// Enables or disables PKCE (with the `S256` method), which is enabled by default.
+    pub fn with_pkce(mut self, pkce: bool) -> WebFlow<C> {
+        self.pkce = pkce;
+        self
+    }
+
+    /// Returns a new authorization request for `scopes`, with a random state, nonce and PKCE
+    /// verifier.
+    pub fn authorization_request<'a, I, T>(
+        &self,
+        scopes: I,
+    ) -> Result<AuthorizationRequest, Box<dyn Error>>
+    where
+        T: AsRef<str> + 'a,
+        I: IntoIterator<Item = &'a T>,
+    {
+        let scopes: Vec<String> = scopes.into_iter().map(|s| s.as_ref().to_string()).collect();
+        let state = random_string()?;
+        let nonce = match self.auth_params.get("nonce") {
+            Some(nonce) => nonce.to_string(),
+            None => random_string()?,
+        };
+        let mut params = self
+            .auth_params
+            .clone()
+            .with_param("state", &state)
+            .with_nonce(&nonce);
+        let code_verifier = if self.pkce {
+            let verifier = random_string()?;
+            let challenge =
+                base64::encode_config(&sha256(verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+            params = params
+                .with_param("code_challenge", challenge)
+                .with_param("code_challenge_method", "S256");
+            Some(verifier)
+        } else {
+            None
+        };
+
+        let url = build_authentication_request_url(
+            &self.secret.auth_uri,
+            &self.secret.client_id,
+            &scopes,
+            Some(self.redirect_uri.clone()),
+            None,
+            &params,
+        )?;
+        Ok(AuthorizationRequest {
+            url,
+            scopes,
+            state,
+            code_verifier,
+            nonce,
+        })
+    }
+
+    /// Exchanges the `code` of the authorization response to `request` for a token, after
+    /// checking that the response carries the `state` of the request. The state is compared in
+    /// constant time.
+    pub fn exchange_code(
+        &mut self,
+        request: &AuthorizationRequest,
+        state: &str,
+        code: &str,
+    ) -> Result<Token, Box<dyn Error>> {
+        if !constant_time_eq(state.as_bytes(), request.state.as_bytes()) {
+            return Err(Box::new(StringError::new(
+                "The state of the authorization response doesn't match the request".to_string(),
+                None,
+            )));
+        }
+        request_token(
+            self.client.borrow_mut(),
+            &self.secret,
+            code,
+            &self.redirect_uri,
+            request.code_verifier.as_deref(),
+        )
+    }
+
+    /// Like `exchange_code()`, but also saves the token in `storage`, for `account` and the
+    /// scopes of `request`. An `Authenticator` using the same secret and storage can then
+    /// provide and refresh the token.
+    pub fn exchange_code_into<S: TokenStorage>(
+        &mut self,
+        request: &AuthorizationRequest,
+        state: &str,
+        code: &str,
+        storage: &mut S,
+        account: &str,
+    ) -> Result<Token, Box<dyn Error>> {
+        let token = self.exchange_code(request, state, code)?;
+        let (hash, scopes) = hash_scopes(&request.scopes);
+        storage.set_for_account(account, hash, &scopes, Some(token.clone()))?;
+        Ok(token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use crate::types::tests::MockConnector;
+    use crate::types::tests::SECRET;
+    use crate::types::ConsoleApplicationSecret;
+    use url::Url;
+
+    const TOKEN_RESPONSE: &str = "HTTP/1.1 200 OK\r\n\
+         \r\n\
+         {\"access_token\":\"web\",\"refresh_token\":\"refresh\",\
+         \"expires_in\":3600,\"token_type\":\"Bearer\"}";
+
+    #[test]
+    fn web_flow() {
+        let secret = serde_json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        let mut flow = WebFlow::new(
+            hyper::Client::with_connector(MockConnector::new(&[TOKEN_RESPONSE])),
+            &secret,
+            "https://example.com/callback",
+        )
+        .with_auth_params(AuthParams::default().offline());
+        let scopes = ["https://www.googleapis.com/auth/drive"];
+        let request = flow.authorization_request(&scopes).unwrap();
+
+        let url = Url::parse(&request.url).unwrap();
+        let param = |name: &str| {
+            url.query_pairs()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.into_owned())
+        };
+        assert_eq!(param("client_id").unwrap(), secret.client_id);
+        assert_eq!(
+            param("redirect_uri").unwrap(),
+            "https://example.com/callback"
+        );
+        assert_eq!(param("access_type").unwrap(), "offline");
+        assert_eq!(param("state").unwrap(), request.state);
+        assert_eq!(param("nonce").unwrap(), request.nonce);
+        let verifier = request.code_verifier.clone().unwrap();
+        assert_eq!(verifier.len(), 43);
+        assert_eq!(
+            param("code_challenge").unwrap(),
+            base64::encode_config(&sha256(verifier.as_bytes()), base64::URL_SAFE_NO_PAD)
+        );
+        assert_eq!(param("code_challenge_method").unwrap(), "S256");
+        assert_ne!(
+            flow.authorization_request(&scopes).unwrap().state,
+            request.state
+        );
+
+        let mut storage = MemoryStorage::default();
+        let mut tampered = request.state.clone();
+        tampered.pop();
+        let extended = request.state.clone() + "x";
+        for forged in &["forged", &request.state[..10], &(tampered + "x"), &extended] {
+            assert!(flow
+                .exchange_code_into(&request, forged, "code", &mut storage, "user")
+                .is_err());
+        }
+        let token = flow
+            .exchange_code_into(&request, &request.state, "code", &mut storage, "user")
+            .unwrap();
+        assert_eq!(token.access_token, "web");
+        let (hash, scps) = hash_scopes(&scopes);
+        assert_eq!(
+            storage.get_for_account("user", hash, &scps).unwrap(),
+            Some(token)
+        );
+    }
+
+    #[test]
+    fn web_flow_login_hint() {
+        let secret = serde_json::from_str::<ConsoleApplicationSecret>(SECRET)
+            .unwrap()
+            .installed
+            .unwrap();
+        let flow = WebFlow::new(
+            hyper::Client::with_connector(MockConnector::new(&[TOKEN_RESPONSE])),
+            &secret,
+            "https://example.com/callback",
+        )
+        .with_auth_params(AuthParams::default().with_param("login_hint", "me@example.com"));
+        let request = flow
+            .authorization_request(&["https://www.googleapis.com/auth/drive"])
+            .unwrap();
+        let url = Url::parse(&request.url).unwrap();
+        let hints: Vec<String> = url
+            .query_pairs()
+            .filter(|(n, _)| n == "login_hint")
+            .map(|(_, v)| v.into_owned())
+            .collect();
+        assert_eq!(hints, vec!["me@example.com".to_string()]);
+    }
+}